@@ -21,10 +21,10 @@ mod ctrl_map;
 use ctrl_map::{CtrlMap, MappingType};
 
 mod midi_handler;
-use midi_handler::{MidiHandler, MidiMessage};
+use midi_handler::{MidiHandler, MidiMessage, MpeConfig, MpeZone};
 
 mod modulation;
-use modulation::ModData;
+use modulation::{ModData, ModCurve, ModPolarity, ModAccumulator, ModSums, ModTable, mod_slots};
 
 mod parameter;
 use parameter::*;
@@ -73,7 +73,7 @@ use std::time::Duration;
 use std::vec::Vec;
 
 pub const SYNTH_ENGINE_VERSION: &str = "0.0.8";
-pub const SOUND_DATA_VERSION: &str = "0.0.8";
+pub const SOUND_DATA_VERSION: &str = "0.0.9";
 
 type Float = f64;
 
@@ -85,6 +85,7 @@ pub enum SynthMessage {
     Wavetable(WtInfo),
     SampleBuffer(Vec<Float>, SynthParam),
     Bpm(Float),
+    Mpe(MpeConfig),
     Exit
 }
 
@@ -116,14 +117,14 @@ fn setup_messaging() -> (Sender<UiMessage>, Receiver<UiMessage>, Sender<SynthMes
     (to_ui_sender, ui_receiver, to_synth_sender, synth_receiver)
 }
 
-fn setup_midi(m2s_sender: Sender<SynthMessage>, m2u_sender: Sender<UiMessage>, midi_port: usize, mut midi_channel: u8) -> Result<MidiInputConnection<()>, ()> {
+fn setup_midi(m2s_sender: Sender<SynthMessage>, m2u_sender: Sender<UiMessage>, midi_port: usize, mut midi_channel: u8, mpe: MpeConfig) -> Result<MidiInputConnection<()>, ()> {
     println!("Setting up MIDI... ");
     if midi_channel < 1 || midi_channel > 16 {
         midi_channel = 16; // Omni
     } else {
         midi_channel -= 1; // 0 - 15
     }
-    let conn_in = MidiHandler::run(m2s_sender, m2u_sender, midi_port, midi_channel);
+    let conn_in = MidiHandler::run(m2s_sender, m2u_sender, midi_port, midi_channel, mpe);
     println!("... finished.");
     conn_in
 }
@@ -195,8 +196,7 @@ fn save_voice() -> std::io::Result<()> {
     let wt = wt_manager.get_table(0).unwrap();
     let mut voice = Voice::new(44100, wt);
     let mut sound_global = SoundData::new();
-    let mut sound_local = SoundData::new();
-    let global_state = SynthState{freq_factor: 1.0};
+    let global_state = SynthState{freq_factor: 1.0, mod_acc: ModAccumulator::new(), modul: ModTable::new()};
 
     sound_global.init();
     sound_global.osc[0].level = 1.0;
@@ -205,6 +205,7 @@ fn save_voice() -> std::io::Result<()> {
     sound_global.env[0].sustain = 1.0;
     sound_global.env[0].factor = 1.0;
     sound_global.filter[0].filter_type = 0; // Bypass
+    let mut sound_local = sound_global;
 
     voice.set_freq(21.533203125);
     voice.trigger(0, 0, &sound_global);
@@ -251,12 +252,34 @@ fn main() {
                             .long("midichannel")
                             .help("Selects the MIDI channel to receive MIDI events on (1 - 16, default = omni)")
                             .takes_value(true))
+                        .arg(Arg::with_name("mpe")
+                            .long("mpe")
+                            .help("Enables MPE mode with the given zone (lower or upper)")
+                            .takes_value(true))
+                        .arg(Arg::with_name("mpechannels")
+                            .long("mpe-channels")
+                            .help("Sets the number of MPE member channels (1 - 15, default 15)")
+                            .takes_value(true))
+                        .arg(Arg::with_name("mpebend")
+                            .long("mpe-bend")
+                            .help("Sets the pitch bend range of MPE notes in semitones (default 48)")
+                            .takes_value(true))
                         .get_matches();
     let midi_port = matches.value_of("midiport").unwrap_or("1");
     let midi_port: usize = midi_port.parse().unwrap_or(1);
     let midi_channel = matches.value_of("midichannel").unwrap_or("0");
     let midi_channel: u8 = midi_channel.parse().unwrap_or(0);
     let show_tui = !matches.is_present("notui");
+    let mut mpe = MpeConfig{..Default::default()};
+    mpe.zone = match matches.value_of("mpe").unwrap_or("") {
+        "lower" => MpeZone::Lower,
+        "upper" => MpeZone::Upper,
+        _ => MpeZone::Off,
+    };
+    let mpe_channels = matches.value_of("mpechannels").unwrap_or("15");
+    mpe.num_channels = mpe_channels.parse::<u8>().unwrap_or(15).clamp(1, 15);
+    let mpe_bend = matches.value_of("mpebend").unwrap_or("48");
+    mpe.bend_range = mpe_bend.parse().unwrap_or(48.0);
 
     // Show version
     if matches.is_present("version") {
@@ -279,7 +302,7 @@ fn main() {
 
     // Do setup
     let (to_ui_sender, ui_receiver, to_synth_sender, synth_receiver) = setup_messaging();
-    let result = setup_midi(to_synth_sender.clone(), to_ui_sender.clone(), midi_port, midi_channel);
+    let result = setup_midi(to_synth_sender.clone(), to_ui_sender.clone(), midi_port, midi_channel, mpe);
     let midi_connection = match result {
         Ok(c) => c,
         Err(()) => return,
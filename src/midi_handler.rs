@@ -24,25 +24,87 @@ pub enum MidiMessage {
     Reset,
 }
 
+/// Layout of an MPE zone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MpeZone {
+    Off,
+    Lower, // Master channel 1, member channels counting up from channel 2
+    Upper, // Master channel 16, member channels counting down from channel 15
+}
+
+/** MPE (MIDI Polyphonic Expression) configuration.
+ *
+ * In MPE mode, every note is played on a separate member channel, which
+ * carries the pitch bend, pressure and timbre (CC 74) of that single note.
+ * Messages on the master channel apply to all notes of the zone.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct MpeConfig {
+    pub zone: MpeZone,
+    pub num_channels: u8,  // Number of member channels (1 - 15)
+    pub bend_range: Float, // Pitch bend range of the member channels in semitones
+}
+
+impl Default for MpeConfig {
+    fn default() -> Self {
+        MpeConfig{zone: MpeZone::Off, num_channels: 15, bend_range: 48.0}
+    }
+}
+
+impl MpeConfig {
+    pub fn is_active(&self) -> bool {
+        self.zone != MpeZone::Off
+    }
+
+    pub fn get_master_channel(&self) -> Option<u8> {
+        match self.zone {
+            MpeZone::Off => None,
+            MpeZone::Lower => Some(0),
+            MpeZone::Upper => Some(15),
+        }
+    }
+
+    pub fn is_member_channel(&self, channel: u8) -> bool {
+        let num_channels = self.num_channels.min(15);
+        match self.zone {
+            MpeZone::Off => false,
+            MpeZone::Lower => channel >= 1 && channel <= num_channels,
+            MpeZone::Upper => channel <= 14 && channel >= 15 - num_channels,
+        }
+    }
+}
+
+// Registered parameter number of the MPE configuration message
+const RPN_MPE_CONFIG: u16 = 6;
+const RPN_NULL: u16 = 0x3FFF;
+
 pub struct MidiHandler {
     last_timestamp: u64,
-    bpm: Float
+    bpm: Float,
+    mpe: MpeConfig,
+    rpn: [u16; 16], // Currently selected registered parameter per channel
 }
 
 impl MidiHandler {
-    fn new() -> Self {
-        MidiHandler{last_timestamp: 0, bpm: 0.0}
+    fn new(mpe: MpeConfig) -> Self {
+        MidiHandler{last_timestamp: 0, bpm: 0.0, mpe, rpn: [RPN_NULL; 16]}
     }
 
     /** Starts the thread for receiving MIDI events.
      *
-     * If the midi_channel argument is 0, all events are forwarded. If it is
-     * between 1 and 16, only events arriving on that channel are forwarded.
+     * If the midi_channel argument is 16, all events are forwarded. If it is
+     * between 0 and 15, only events arriving on that channel are forwarded.
+     *
+     * If an MPE zone is configured, either with the mpe argument or by an
+     * MPE configuration message from the controller, the master and member
+     * channels of the zone are forwarded instead. Controllers on member
+     * channels are sent to the synth engine as per-note expression.
      */
     pub fn run(m2s_sender: Sender<SynthMessage>,
                m2u_sender: Sender<UiMessage>,
                midi_port: usize,
-               midi_channel: u8) -> Result<MidiInputConnection<()>, ()> {
+               midi_channel: u8,
+               mpe: MpeConfig) -> Result<MidiInputConnection<()>, ()> {
         let result = MidiInput::new("Yazz MIDI input");
         let mut midi_in = match result {
             Ok(m) => m,
@@ -62,16 +124,32 @@ impl MidiHandler {
                 return Err(());
             }
         };
-        let mut mh = MidiHandler::new();
+        let mut mh = MidiHandler::new(mpe);
+        m2s_sender.send(SynthMessage::Mpe(mpe)).unwrap();
         info!("  Connecting to MIDI port {}", in_port_name);
         println!("  Connecting to MIDI port {}", in_port_name);
         let conn_result = midi_in.connect(midi_port, "midir-read-input", move |timestamp, message, _| {
-            if midi_channel < 16 && (message[0] & 0x0F) != midi_channel {
+            // Drop messages we don't handle (SysEx, MTC, etc.)
+            let m = match MidiHandler::get_midi_message(message) {
+                Some(m) => m,
+                None => return,
+            };
+            if let MidiMessage::ControlChg{channel, controller, value} = m {
+                if mh.handle_mpe_config(channel, controller, value) {
+                    info!("MPE configuration: {:?}", mh.mpe);
+                    m2s_sender.send(SynthMessage::Mpe(mh.mpe)).unwrap();
+                }
+            }
+            // System messages (clock, transport) don't have a channel
+            if message[0] < 0xF0 && !mh.accepts_channel(message[0] & 0x0F, midi_channel) {
                 return;
             }
-            let m = MidiHandler::get_midi_message(message);
             info!("MidiMessage: {:?}", m);
             match m {
+                MidiMessage::ControlChg{channel, controller: _, value: _} if mh.mpe.is_member_channel(channel) => {
+                    // Per-note expression goes directly to the synth engine
+                    m2s_sender.send(SynthMessage::Midi(m)).unwrap();
+                }
                 MidiMessage::ControlChg{channel: _, controller: _, value: _} |
                 MidiMessage::ProgramChg{channel: _, program: _} => {
                     // Send control change and program change to UI
@@ -83,6 +161,8 @@ impl MidiHandler {
                     if bpm_changed {
                         m2s_sender.send(SynthMessage::Bpm(mh.bpm)).unwrap();
                     }
+                    // The synth counts clock ticks for the song position
+                    m2s_sender.send(SynthMessage::Midi(m)).unwrap();
                 }
                 _ => {
                     // Send everything else directly to the synth engine
@@ -99,11 +179,19 @@ impl MidiHandler {
         }
     }
 
-    pub fn get_midi_message(message: &[u8]) -> MidiMessage {
+    /** Parse a raw MIDI message.
+     *
+     * Returns None for empty messages and status bytes that the synth doesn't
+     * handle, like SysEx or MIDI time code.
+     */
+    pub fn get_midi_message(message: &[u8]) -> Option<MidiMessage> {
+        if message.is_empty() {
+            return None;
+        }
         let param = if message.len() > 1 { message[1] } else { 0 };
         let value = if message.len() > 2 { message[2] } else { 0 };
 
-        match message[0] {
+        let m = match message[0] {
             0xF2 => {
                 let mut position: u16 = param as u16;
                 position |= (value as u16) << 7;
@@ -115,6 +203,7 @@ impl MidiHandler {
             0xFC => MidiMessage::Stop,
             0xFE => MidiMessage::ActiveSensing,
             0xFF => MidiMessage::Reset,
+            0xF0..=0xFF => return None, // Other system messages
             _ => {
                 let channel = message[0] & 0x0F;
                 match message[0] & 0xF0 {
@@ -130,12 +219,47 @@ impl MidiHandler {
                         pitch -= 0x2000;
                         MidiMessage::Pitchbend{channel, pitch}
                     },
-                    _ => panic!(),
+                    _ => return None, // Data byte without status
                 }
             }
+        };
+        Some(m)
+    }
+
+    fn accepts_channel(&self, channel: u8, midi_channel: u8) -> bool {
+        if self.mpe.is_active() {
+            self.mpe.get_master_channel() == Some(channel) || self.mpe.is_member_channel(channel)
+        } else {
+            midi_channel >= 16 || channel == midi_channel
         }
     }
 
+    // Track registered parameter selection and look for the MPE
+    // configuration message (RPN 6) on channel 1 or 16. Returns true if the
+    // MPE configuration has changed.
+    fn handle_mpe_config(&mut self, channel: u8, controller: u8, value: u8) -> bool {
+        let rpn = &mut self.rpn[channel as usize & 0x0F];
+        match controller {
+            101 => *rpn = (*rpn & 0x7F) | ((value as u16) << 7),
+            100 => *rpn = (*rpn & 0x3F80) | value as u16,
+            6 if *rpn == RPN_MPE_CONFIG && (channel == 0 || channel == 15) => {
+                // Data entry MSB holds the number of member channels, 0
+                // disables the zone.
+                self.mpe.zone = match (channel, value) {
+                    (_, 0) => MpeZone::Off,
+                    (0, _) => MpeZone::Lower,
+                    _ => MpeZone::Upper,
+                };
+                if value > 0 {
+                    self.mpe.num_channels = value.min(15);
+                }
+                return true;
+            }
+            _ => (),
+        }
+        false
+    }
+
     fn calc_bpm(&mut self, timestamp: u64) -> bool {
         let mut bpm_changed = false;
         if self.last_timestamp != 0 {
@@ -155,3 +279,47 @@ impl MidiHandler {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mpe_zones_select_member_channels() {
+        let mut mpe = MpeConfig{zone: MpeZone::Lower, num_channels: 3, bend_range: 48.0};
+        assert!(!mpe.is_member_channel(0));
+        assert!(mpe.is_member_channel(1));
+        assert!(mpe.is_member_channel(3));
+        assert!(!mpe.is_member_channel(4));
+        mpe.zone = MpeZone::Upper;
+        assert!(mpe.is_member_channel(14));
+        assert!(mpe.is_member_channel(12));
+        assert!(!mpe.is_member_channel(11));
+        assert!(!mpe.is_member_channel(15));
+    }
+
+    #[test]
+    fn unknown_messages_are_dropped() {
+        assert!(MidiHandler::get_midi_message(&[]).is_none());
+        assert!(MidiHandler::get_midi_message(&[0xF0, 0x7E, 0x7F, 0xF7]).is_none()); // SysEx
+        assert!(MidiHandler::get_midi_message(&[0xF1, 0x10]).is_none()); // MTC
+        assert!(MidiHandler::get_midi_message(&[0xF3, 0x01]).is_none()); // Song select
+        assert!(MidiHandler::get_midi_message(&[0xF6]).is_none()); // Tune request
+        assert!(MidiHandler::get_midi_message(&[0x40, 0x40]).is_none()); // Running status data
+        assert!(matches!(MidiHandler::get_midi_message(&[0x91, 60, 100]),
+                         Some(MidiMessage::NoteOn{channel: 1, key: 60, velocity: 100})));
+        assert!(matches!(MidiHandler::get_midi_message(&[0xF8]), Some(MidiMessage::TimingClock)));
+    }
+
+    #[test]
+    fn mpe_configuration_message_sets_zone() {
+        let mut mh = MidiHandler::new(MpeConfig{..Default::default()});
+        assert!(!mh.handle_mpe_config(15, 101, 0));
+        assert!(!mh.handle_mpe_config(15, 100, 6));
+        assert!(mh.handle_mpe_config(15, 6, 7));
+        assert_eq!(mh.mpe.zone, MpeZone::Upper);
+        assert_eq!(mh.mpe.num_channels, 7);
+        assert!(mh.handle_mpe_config(15, 6, 0));
+        assert_eq!(mh.mpe.zone, MpeZone::Off);
+    }
+}
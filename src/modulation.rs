@@ -1,8 +1,9 @@
 use super::Float;
 use super::{Parameter, MenuItem, ValueRange};
-use super::{ParamId, FunctionId};
+use super::{ParamId, FunctionId, SynthParam};
+use super::SoundData;
 use super::voice::{NUM_OSCILLATORS, NUM_ENVELOPES, NUM_LFOS};
-use super::synth::NUM_GLOBAL_LFOS;
+use super::synth::{NUM_GLOBAL_LFOS, NUM_MODULATORS, NUM_MACROS};
 
 use log::info;
 use serde::{Serialize, Deserialize};
@@ -29,20 +30,112 @@ pub struct ModSource {
 }
 
 /** Static list of available modulation data sources. */
-static MOD_SOURCE: [ModSource; 9] = [
+static MOD_SOURCE: [ModSource; 20] = [
+    ModSource{function: Parameter::None,        index_range: (1, 1),               val_range: ValueRange::Float(0.0, 1.0, 0.01),  is_global: true},
     ModSource{function: Parameter::GlobalLfo,   index_range: (1, NUM_GLOBAL_LFOS), val_range: ValueRange::Float(-1.0, 1.0, 0.1),  is_global: true},
     ModSource{function: Parameter::Aftertouch,  index_range: (1, 1),               val_range: ValueRange::Float(0.0, 1.0, 0.1),   is_global: true},
     ModSource{function: Parameter::Pitchbend,   index_range: (1, 1),               val_range: ValueRange::Float(-1.0, 1.0, 0.01), is_global: true},
     ModSource{function: Parameter::ModWheel,    index_range: (1, 1),               val_range: ValueRange::Float(0.0, 127.0, 0.1), is_global: true},
     ModSource{function: Parameter::SustainPedal,index_range: (1, 1),               val_range: ValueRange::Float(0.0, 1.0, 1.0),   is_global: true},
+    ModSource{function: Parameter::Controller,  index_range: (0, 127),             val_range: ValueRange::Float(0.0, 1.0, 0.01),  is_global: true},
+    ModSource{function: Parameter::OutputFollower, index_range: (1, 1),            val_range: ValueRange::Float(0.0, 1.0, 0.01), is_global: true},
+    ModSource{function: Parameter::Macro,       index_range: (1, NUM_MACROS),      val_range: ValueRange::Float(0.0, 1.0, 0.01), is_global: true},
 
     ModSource{function: Parameter::Envelope,    index_range: (1, NUM_ENVELOPES),   val_range: ValueRange::Float(0.0, 1.0, 0.01),  is_global: false},
     ModSource{function: Parameter::Lfo,         index_range: (1, NUM_LFOS),        val_range: ValueRange::Float(-1.0, 1.0, 0.01), is_global: false},
+    ModSource{function: Parameter::Follower,    index_range: (1, 1),               val_range: ValueRange::Float(0.0, 1.0, 0.01), is_global: false},
     ModSource{function: Parameter::Oscillator,  index_range: (1, NUM_OSCILLATORS), val_range: ValueRange::Float(-1.0, 1.0, 0.01), is_global: false},
     ModSource{function: Parameter::Velocity,    index_range: (1, 1),               val_range: ValueRange::Float(0.0, 1.0, 0.1), is_global: false},
+    ModSource{function: Parameter::KeyAftertouch, index_range: (1, 1),             val_range: ValueRange::Float(0.0, 1.0, 0.01), is_global: false},
+    ModSource{function: Parameter::Timbre,      index_range: (1, 1),               val_range: ValueRange::Float(0.0, 1.0, 0.01), is_global: false},
+    ModSource{function: Parameter::KeyValue,    index_range: (1, 1),               val_range: ValueRange::Float(0.0, 127.0, 1.0), is_global: false},
+    ModSource{function: Parameter::Random,      index_range: (1, 1),               val_range: ValueRange::Float(-1.0, 1.0, 0.01), is_global: false},
+    ModSource{function: Parameter::RelVelocity, index_range: (1, 1),               val_range: ValueRange::Float(0.0, 1.0, 0.01), is_global: false},
+    ModSource{function: Parameter::VoiceIndex,  index_range: (1, 1),               val_range: ValueRange::Float(0.0, 1.0, 0.01), is_global: false},
 ];
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
+// Number of output levels of the stepped response curve
+const NUM_CURVE_STEPS: Float = 8.0;
+
+// Steepness of the exponential response curve
+const EXP_CURVE_STEEPNESS: Float = 4.0;
+
+/** Response curve applied to the modulation source. */
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum ModCurve {
+    Linear,
+    Exponential,
+    SCurve,
+    Stepped,
+}
+
+impl ModCurve {
+    pub fn from_int(param: usize) -> ModCurve {
+        match param {
+            0 => ModCurve::Linear,
+            1 => ModCurve::Exponential,
+            2 => ModCurve::SCurve,
+            3 => ModCurve::Stepped,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            ModCurve::Linear => 0,
+            ModCurve::Exponential => 1,
+            ModCurve::SCurve => 2,
+            ModCurve::Stepped => 3,
+        }
+    }
+
+    // Apply the curve to a value in the range 0.0 - 1.0.
+    fn apply(&self, value: Float) -> Float {
+        match self {
+            ModCurve::Linear => value,
+            ModCurve::Exponential => ((EXP_CURVE_STEEPNESS * value).exp() - 1.0) / (EXP_CURVE_STEEPNESS.exp() - 1.0),
+            ModCurve::SCurve => value * value * (3.0 - 2.0 * value),
+            ModCurve::Stepped => (value * NUM_CURVE_STEPS).floor().min(NUM_CURVE_STEPS - 1.0) / (NUM_CURVE_STEPS - 1.0),
+        }
+    }
+}
+
+impl Default for ModCurve {
+    fn default() -> Self { ModCurve::Linear }
+}
+
+/** Polarity of the modulation source output. */
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum ModPolarity {
+    Source,   // Keep the polarity of the source
+    Unipolar, // 0.0 to 1.0 of the source range
+    Bipolar,  // -0.5 to 0.5 of the source range
+}
+
+impl ModPolarity {
+    pub fn from_int(param: usize) -> ModPolarity {
+        match param {
+            0 => ModPolarity::Source,
+            1 => ModPolarity::Unipolar,
+            2 => ModPolarity::Bipolar,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            ModPolarity::Source => 0,
+            ModPolarity::Unipolar => 1,
+            ModPolarity::Bipolar => 2,
+        }
+    }
+}
+
+impl Default for ModPolarity {
+    fn default() -> Self { ModPolarity::Source }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
 pub struct ModData {
     pub source_func: Parameter,
     pub source_func_id: usize,
@@ -53,20 +146,55 @@ pub struct ModData {
     pub active: bool,
     pub is_global: bool,
     pub scale: Float,
+    #[serde(default)]
+    pub curve: ModCurve,
+    #[serde(default)]
+    pub polarity: ModPolarity,
+    #[serde(default)]
+    pub offset: Float, // Added to the source value, as part of the source range
+    #[serde(default = "ModData::default_via")]
+    pub via_func: Parameter, // Source scaling the amount, None if unused
+    #[serde(default)]
+    pub via_func_id: usize,
+
+    // Values derived from the source ranges by update()
+    #[serde(skip)]
+    range_scale: Float, // Scale for an amount of 1.0
+    #[serde(skip)]
+    source_min: Float,
+    #[serde(skip)]
+    source_range: Float,
+    #[serde(skip)]
+    via_min: Float,
+    #[serde(skip)]
+    via_range: Float,
+    #[serde(skip)]
+    pub source_is_global: bool,
+    #[serde(skip)]
+    pub via_is_global: bool,
+
+    // Current values of global sources, set by the synth for the voices
+    #[serde(skip)]
+    pub source_value: Float,
+    #[serde(skip)]
+    pub via_value: Float,
 }
 
 impl ModData {
     pub fn new() -> ModData {
-        let source_func = Parameter::Lfo;
-        let source_func_id = 1;
-        let target_func = Parameter::Oscillator;
-        let target_func_id = 1;
-        let target_param = Parameter::Level;
-        let amount = 0.0;
-        let active = false;
-        let is_global = false;
-        let scale = 0.0;
-        ModData{source_func, source_func_id, target_func, target_func_id, target_param, amount, active, is_global, scale}
+        ModData{
+            source_func: Parameter::Lfo,
+            source_func_id: 1,
+            target_func: Parameter::Oscillator,
+            target_func_id: 1,
+            target_param: Parameter::Level,
+            via_func: Parameter::None,
+            ..Default::default()
+        }
+    }
+
+    fn default_via() -> Parameter {
+        Parameter::None
     }
 
     pub fn set_source(&mut self, func: &FunctionId) {
@@ -82,6 +210,12 @@ impl ModData {
         self.update();
     }
 
+    pub fn set_via(&mut self, func: &FunctionId) {
+        self.via_func = func.function;
+        self.via_func_id = func.function_id;
+        self.update();
+    }
+
     pub fn set_amount(&mut self, amount: Float) {
         self.amount = amount;
         self.update();
@@ -99,15 +233,56 @@ impl ModData {
         // Calculate scale factor
         // Scale is the factor applied to the mod source value to cover the
         // total target value range. Mod amount limits it to a smaller range.
-        self.scale = ((dest_max - dest_min) / (source_max - source_min)) * self.amount;
-        self.is_global = source.is_global;
+        self.range_scale = (dest_max - dest_min) / (source_max - source_min);
+        self.scale = self.range_scale * self.amount;
+        self.source_min = source_min;
+        self.source_range = source_max - source_min;
+        self.source_is_global = source.is_global;
+
+        // Via source
+        let via = ModData::get_mod_source(self.via_func);
+        let (via_min, via_max) = via.val_range.get_min_max();
+        self.via_min = via_min;
+        self.via_range = via_max - via_min;
+        self.via_is_global = via.is_global;
+
+        // Only global if no voice-specific values are needed
+        self.is_global = self.source_is_global && self.via_is_global;
         info!("Updated modulator {:?}", self);
     }
 
+    /** Calculate the value to add to the target from the source value.
+     *
+     * Applies offset, response curve and polarity to the source value, scales
+     * it by the via source and converts it to the target range.
+     */
+    pub fn get_mod_value(&self, source_value: Float, via_value: Float) -> Float {
+        let mut value = if self.curve == ModCurve::Linear && self.polarity == ModPolarity::Source && self.offset == 0.0 {
+            source_value
+        } else {
+            let normalized = (source_value - self.source_min) / self.source_range + self.offset;
+            let shaped = self.curve.apply(normalized.clamp(0.0, 1.0));
+            let base = match self.polarity {
+                ModPolarity::Source => self.source_min,
+                ModPolarity::Unipolar => 0.0,
+                ModPolarity::Bipolar => -0.5 * self.source_range,
+            };
+            base + shaped * self.source_range
+        };
+        if self.via_func != Parameter::None {
+            value *= ((via_value - self.via_min) / self.via_range).clamp(0.0, 1.0);
+        }
+        value * self.scale
+    }
+
     pub fn get_source(&self) -> FunctionId {
         FunctionId{function: self.source_func, function_id: self.source_func_id}
     }
 
+    pub fn get_via(&self) -> FunctionId {
+        FunctionId{function: self.via_func, function_id: self.via_func_id}
+    }
+
     pub fn get_target(&self) -> ParamId {
         ParamId{function: self.target_func, function_id: self.target_func_id, parameter: self.target_param}
     }
@@ -122,3 +297,447 @@ impl ModData {
     }
 }
 
+/** The modulators of the sound played by the synth.
+ *
+ * Kept separately from the sound data, so that the per-sample copies of the
+ * sound don't include the modulator slots. Also holds the bitmap of active
+ * modulators, so that unused slots are skipped.
+ */
+#[derive(Clone, Copy)]
+pub struct ModTable {
+    pub modul: [ModData; NUM_MODULATORS],
+    active: u64, // Bitmap of active modulators
+}
+
+impl ModTable {
+    pub fn new() -> Self {
+        ModTable{modul: [ModData::new(); NUM_MODULATORS], active: 0}
+    }
+
+    /** Take over all modulators of a sound and update their derived values. */
+    pub fn load(&mut self, modul: &[ModData; NUM_MODULATORS]) {
+        self.active = 0;
+        for (i, m) in modul.iter().enumerate() {
+            self.set(i, m);
+        }
+    }
+
+    fn set_active(&mut self, id: usize, active: bool) {
+        self.modul[id].active = active;
+        if active {
+            self.active |= 1 << id;
+        } else {
+            self.active &= !(1 << id);
+        }
+    }
+
+    /** Take over a single changed modulator. */
+    pub fn set(&mut self, id: usize, data: &ModData) {
+        let m = &mut self.modul[id];
+        *m = *data;
+        if *m != ModData::new() {
+            m.update();
+        }
+        if m.active {
+            self.active |= 1 << id;
+        } else {
+            self.active &= !(1 << id);
+        }
+    }
+
+    /** Iterate over the indexes of the active modulators. */
+    pub fn get_active_ids(&self) -> impl Iterator<Item = usize> {
+        let mut mask = self.active;
+        std::iter::from_fn(move || {
+            if mask == 0 {
+                return None;
+            }
+            let i = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            Some(i)
+        })
+    }
+}
+
+impl Default for ModTable {
+    fn default() -> Self { ModTable::new() }
+}
+
+/** Summed modulation values per target slot. */
+#[derive(Clone, Copy)]
+pub struct ModSums {
+    sum: [Float; NUM_MODULATORS], // Summed modulation values of each slot
+    changed: u64,                 // Bitmap of slots with new values
+}
+
+impl ModSums {
+    pub fn new() -> Self {
+        ModSums{sum: [0.0; NUM_MODULATORS], changed: 0}
+    }
+
+    /** Discard the values of the previous sample. */
+    pub fn reset(&mut self) {
+        let mut mask = self.changed;
+        while mask != 0 {
+            self.sum[mask.trailing_zeros() as usize] = 0.0;
+            mask &= mask - 1;
+        }
+        self.changed = 0;
+    }
+
+    /** Bitmap of the slots that have values for the current sample. */
+    pub fn get_changed(&self) -> u64 {
+        self.changed
+    }
+
+    fn add(&mut self, slot: usize, value: Float) {
+        self.sum[slot] += value;
+        self.changed |= 1 << slot;
+    }
+}
+
+impl Default for ModSums {
+    fn default() -> Self { ModSums::new() }
+}
+
+/** Sums up the modulation values per target parameter.
+ *
+ * Modulators with the same target share a slot. The sums are added to the
+ * unmodulated patch value once per target, so the patch is never overwritten
+ * and integer targets are rounded only once.
+ *
+ * The accumulator holds the sums of the global modulators. Voices keep the
+ * sums of their local modulators in a separate ModSums, which is added to the
+ * global sums when applying them.
+ */
+#[derive(Clone, Copy)]
+pub struct ModAccumulator {
+    slot: [usize; NUM_MODULATORS],     // Slot used by each modulator
+    target: [ParamId; NUM_MODULATORS], // Target parameter of each slot
+    modul_slots: u64,                  // Bitmap of slots targeting other modulators
+    sums: ModSums,                     // Sums of the global modulators
+}
+
+impl ModAccumulator {
+    pub fn new() -> Self {
+        ModAccumulator{
+            slot: [0; NUM_MODULATORS],
+            target: [ParamId::default(); NUM_MODULATORS],
+            modul_slots: 0,
+            sums: ModSums::new(),
+        }
+    }
+
+    /** Assign the slots according to the modulator targets. */
+    pub fn update(&mut self, modul: &[ModData; NUM_MODULATORS]) {
+        self.modul_slots = 0;
+        for (i, m) in modul.iter().enumerate() {
+            let target = m.get_target();
+            let slot = modul[..i].iter().position(|other| other.get_target() == target).unwrap_or(i);
+            self.slot[i] = slot;
+            self.target[slot] = target;
+            if target.function == Parameter::Modulation {
+                self.modul_slots |= 1 << slot;
+            }
+        }
+        self.sums = ModSums::new();
+    }
+
+    /** Discard the values of the previous sample. */
+    pub fn reset(&mut self) {
+        self.sums.reset();
+    }
+
+    /** Bitmap of the slots modulated globally in the current sample. */
+    pub fn get_changed(&self) -> u64 {
+        self.sums.changed
+    }
+
+    /** Add the output of a global modulator to the sum of its target. */
+    pub fn add(&mut self, modulator: usize, value: Float) {
+        self.sums.add(self.slot[modulator], value);
+    }
+
+    /** Add the output of a voice-local modulator to the voice sums. */
+    pub fn add_local(&self, local: &mut ModSums, modulator: usize, value: Float) {
+        local.add(self.slot[modulator], value);
+    }
+
+    /** Write the modulated values of all globally changed targets to the sound.
+     *
+     * The base values are taken from the unmodulated patch.
+     */
+    pub fn apply(&self, patch: &SoundData, sound: &mut SoundData) {
+        let mut synth_param = SynthParam{..Default::default()};
+        let mut mask = self.sums.changed & !self.modul_slots;
+        while mask != 0 {
+            let slot = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            self.set_target(slot, patch, self.sums.sum[slot], sound, &mut synth_param);
+        }
+    }
+
+    /** Write the targets of the local modulators of a voice to the sound.
+     *
+     * The values include the global modulation of the same targets. Other
+     * modulators can only be modulated by global modulators, local values
+     * for them are ignored.
+     */
+    pub fn apply_local(&self, local: &ModSums, patch: &SoundData, sound: &mut SoundData) {
+        let mut synth_param = SynthParam{..Default::default()};
+        let mut mask = local.changed & !self.modul_slots;
+        while mask != 0 {
+            let slot = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            self.set_target(slot, patch, self.sums.sum[slot] + local.sum[slot], sound, &mut synth_param);
+        }
+    }
+
+    /** Copy the values of the targets of the given slots between sounds.
+     *
+     * Used to undo modulation of the previous sample without copying the
+     * whole sound.
+     */
+    pub fn copy_targets(&self, mask: u64, from: &SoundData, to: &mut SoundData) {
+        let mut synth_param = SynthParam{..Default::default()};
+        let mut mask = mask & !self.modul_slots;
+        while mask != 0 {
+            let slot = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            let target = &self.target[slot];
+            synth_param.set(target.function, target.function_id, target.parameter, from.get_value(target));
+            to.set_parameter(&synth_param);
+        }
+    }
+
+    /** Write the globally modulated Amount, Offset and Active values to the
+     * modulators in the table.
+     *
+     * Modulators are evaluated from the table only, so their modulation is
+     * not written to the sound copies. Targets without modulation in the
+     * current sample get the patch value back.
+     */
+    pub fn apply_modulators(&self, patch: &SoundData, table: &mut ModTable) {
+        let mut mask = self.modul_slots;
+        while mask != 0 {
+            let slot = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            let target = &self.target[slot];
+            let id = target.function_id - 1;
+            let dest_range = MenuItem::get_val_range(target.function, target.parameter);
+            let value = dest_range.add_modulation(patch.get_value(target), self.sums.sum[slot]).as_float();
+            match target.parameter {
+                Parameter::Amount => {
+                    let m = &mut table.modul[id];
+                    m.amount = value;
+                    m.scale = m.range_scale * value;
+                }
+                Parameter::Offset => table.modul[id].offset = value,
+                Parameter::Active => table.set_active(id, value > 0.0),
+                _ => (),
+            }
+        }
+    }
+
+    fn set_target(&self, slot: usize, patch: &SoundData, sum: Float, sound: &mut SoundData, synth_param: &mut SynthParam) {
+        let target = &self.target[slot];
+        let base = patch.get_value(target);
+        let dest_range = MenuItem::get_val_range(target.function, target.parameter);
+        synth_param.set(target.function, target.function_id, target.parameter, dest_range.add_modulation(base, sum));
+        sound.set_parameter(synth_param);
+    }
+}
+
+impl Default for ModAccumulator {
+    fn default() -> Self { ModAccumulator::new() }
+}
+
+/** Serialization of the modulator slots of a sound.
+ *
+ * Only slots that are in use are stored, together with their slot index.
+ * Older sound files stored all slots as a plain list without index, these are
+ * read by position.
+ */
+pub mod mod_slots {
+    use super::{ModData, NUM_MODULATORS};
+
+    use serde::{Serialize, Deserialize, Serializer, Deserializer};
+    use serde::de::Error;
+
+    #[derive(Serialize)]
+    struct SlotRef<'a> {
+        slot: usize,
+        #[serde(flatten)]
+        data: &'a ModData,
+    }
+
+    #[derive(Deserialize)]
+    struct Slot {
+        #[serde(default)]
+        slot: Option<usize>,
+        #[serde(flatten)]
+        data: ModData,
+    }
+
+    pub fn serialize<S: Serializer>(modul: &[ModData; NUM_MODULATORS], serializer: S) -> Result<S::Ok, S::Error> {
+        let unused = ModData::new();
+        serializer.collect_seq(modul.iter()
+                                    .enumerate()
+                                    .filter(|(_, m)| **m != unused)
+                                    .map(|(slot, data)| SlotRef{slot, data}))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[ModData; NUM_MODULATORS], D::Error> {
+        let slots: Vec<Slot> = Vec::deserialize(deserializer)?;
+        let mut modul = [ModData::new(); NUM_MODULATORS];
+        for (i, s) in slots.into_iter().enumerate() {
+            let slot = s.slot.unwrap_or(i);
+            if slot >= NUM_MODULATORS {
+                return Err(D::Error::custom(format!("invalid modulator slot {}", slot)));
+            }
+            modul[slot] = s.data;
+        }
+        Ok(modul)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct TestSound {
+        #[serde(with = "mod_slots")]
+        modul: [ModData; NUM_MODULATORS],
+    }
+
+    #[test]
+    fn only_used_slots_are_stored() {
+        let mut sound = TestSound{modul: [ModData::new(); NUM_MODULATORS]};
+        sound.modul[40].active = true;
+        sound.modul[40].amount = 0.5;
+        let serialized = serde_json::to_string(&sound).unwrap();
+        assert_eq!(serialized.matches("slot").count(), 1);
+
+        let loaded: TestSound = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(loaded.modul[40], sound.modul[40]);
+        assert_eq!(loaded.modul[0], ModData::new());
+    }
+
+    #[test]
+    fn slots_without_index_are_read_by_position() {
+        let mut m = ModData::new();
+        m.active = true;
+        let old_format = serde_json::to_string(&vec!(ModData::new(), m)).unwrap();
+        let serialized = format!("{{\"modul\":{}}}", old_format);
+        let loaded: TestSound = serde_json::from_str(&serialized).unwrap();
+        assert!(!loaded.modul[0].active);
+        assert!(loaded.modul[1].active);
+        assert!(!loaded.modul[2].active);
+    }
+
+    #[test]
+    fn polarity_and_via_shape_mod_value() {
+        let mut m = ModData::new();
+        m.set_amount(1.0);
+        let scale = m.scale;
+        assert_eq!(m.get_mod_value(-1.0, 0.0), -scale);
+
+        m.polarity = ModPolarity::Unipolar;
+        assert_eq!(m.get_mod_value(-1.0, 0.0), 0.0);
+        assert_eq!(m.get_mod_value(1.0, 0.0), 2.0 * scale);
+
+        m.set_via(&FunctionId{function: Parameter::Aftertouch, function_id: 1});
+        assert!(!m.is_global);
+        assert_eq!(m.get_mod_value(1.0, 0.5), scale);
+    }
+
+    #[test]
+    fn offset_keeps_range_for_all_curves() {
+        let mut m = ModData::new();
+        m.set_amount(1.0);
+        m.offset = 0.5;
+        let scale = m.scale;
+        for curve in [ModCurve::Linear, ModCurve::SCurve].iter() {
+            m.curve = *curve;
+            assert_eq!(m.get_mod_value(-1.0, 0.0), 0.0);
+            assert_eq!(m.get_mod_value(0.5, 0.0), scale); // Clamped to the source range
+            assert_eq!(m.get_mod_value(1.0, 0.0), scale);
+        }
+    }
+
+    #[test]
+    fn table_tracks_active_modulators() {
+        let mut modul = [ModData::new(); NUM_MODULATORS];
+        modul[3].active = true;
+        modul[40].active = true;
+        let mut table = ModTable::new();
+        table.load(&modul);
+        assert_eq!(table.get_active_ids().collect::<Vec<usize>>(), vec!(3, 40));
+
+        modul[3].active = false;
+        table.set(3, &modul[3]);
+        assert_eq!(table.get_active_ids().collect::<Vec<usize>>(), vec!(40));
+    }
+
+    #[test]
+    fn stepped_curve_quantizes_source() {
+        assert_eq!(ModCurve::Stepped.apply(0.0), 0.0);
+        assert_eq!(ModCurve::Stepped.apply(0.1), 0.0);
+        assert_eq!(ModCurve::Stepped.apply(1.0), 1.0);
+        assert_eq!(ModCurve::SCurve.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn int_targets_are_rounded_once() {
+        let mut patch = SoundData::new();
+        patch.init();
+        let tune = ParamId::new(Parameter::Oscillator, 1, Parameter::Tune);
+        patch.modul[0].set_target(&tune);
+        patch.modul[1].set_target(&tune);
+        let mut acc = ModAccumulator::new();
+        acc.update(&patch.modul);
+
+        let mut sound = patch;
+        acc.add(0, 0.4);
+        acc.add(1, 0.4);
+        acc.apply(&patch, &mut sound);
+        assert_eq!(sound.get_value(&tune).as_float(), 1.0);
+        assert_eq!(patch.get_value(&tune).as_float(), 0.0);
+
+        acc.reset();
+        acc.add(0, -0.6);
+        acc.apply(&patch, &mut sound);
+        assert_eq!(sound.get_value(&tune).as_float(), -1.0);
+    }
+
+    #[test]
+    fn local_sums_add_to_global_and_targets_are_restored() {
+        let mut patch = SoundData::new();
+        patch.init();
+        let level = ParamId::new(Parameter::Oscillator, 1, Parameter::Level);
+        let tune = ParamId::new(Parameter::Oscillator, 2, Parameter::Tune);
+        patch.modul[0].set_target(&level);
+        patch.modul[1].set_target(&level);
+        patch.modul[2].set_target(&tune);
+        let mut acc = ModAccumulator::new();
+        acc.update(&patch.modul);
+        let base_level = patch.get_value(&level).as_float();
+
+        let mut global = patch;
+        acc.add(0, 0.1);
+        acc.apply(&patch, &mut global);
+        let mut local = global;
+        let mut sums = ModSums::new();
+        acc.add_local(&mut sums, 1, 0.2);
+        acc.add_local(&mut sums, 2, 1.0);
+        acc.apply_local(&sums, &patch, &mut local);
+        assert!((local.get_value(&level).as_float() - (base_level + 0.3)).abs() < 0.0001);
+        assert_eq!(local.get_value(&tune).as_float(), 1.0);
+
+        // Restoring the changed targets brings back the global values
+        acc.copy_targets(sums.get_changed(), &global, &mut local);
+        assert!((local.get_value(&level).as_float() - (base_level + 0.1)).abs() < 0.0001);
+        assert_eq!(local.get_value(&tune).as_float(), 0.0);
+    }
+}
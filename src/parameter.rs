@@ -22,6 +22,7 @@ pub enum Parameter {
     Modulation,
     Delay,
     Patch,
+    Macro,
     System,
 
     // Oscillator, Lfo
@@ -34,6 +35,8 @@ pub enum Parameter {
     Finetune,
     Sync,
     KeyFollow,
+    KeyCenter,
+    EnvSource,
     Routing,
     Voices,
     Spread,
@@ -53,6 +56,8 @@ pub enum Parameter {
     Resonance,
     Gain,
     Aux,
+    FmSource,
+    FmAmount,
     // Filter types
     RLPF,
     ResonZ,
@@ -62,6 +67,7 @@ pub enum Parameter {
     SEM_BPF,
     SEM_HPF,
     SEM_BSF,
+    SEM_SVF,
     K35_LPF,
     K35_HPF,
     OM_LPF,
@@ -82,12 +88,42 @@ pub enum Parameter {
     Factor,
     Loop,
     ResetToZero,
+    Hold,
+    AttackCurve,
+    DecayCurve,
+    ReleaseCurve,
+    Mseg,
+    NumPoints,
+    EditPoint,
+    PointTime,
+    PointLevel,
+    PointCurve,
+    LoopStart,
+    LoopEnd,
+    AttackSync,
+    DecaySync,
+    ReleaseSync,
+    VelAttack,
+    VelLevel,
+    KeyDecay,
 
     // Mod
     Source,
     Target,
     Amount,
     Active,
+    Curve,
+    Polarity,
+    Offset,
+    Via,
+    // Mod curves
+    Linear,
+    Exponential,
+    SCurve,
+    Stepped,
+    // Mod polarity
+    Unipolar,
+    Bipolar,
 
     // Waveforms
     Sine,
@@ -97,6 +133,19 @@ pub enum Parameter {
     Square,
     Noise,
     SampleHold,
+    Steps,
+
+    // LFO
+    FadeIn,
+    Retrigger,
+    NumSteps,
+    EditStep,
+    StepValue,
+    Smoothing,
+    // LFO retrigger modes
+    Free,
+    Note,
+    OneShot,
 
     // Delay
     Time,
@@ -109,11 +158,17 @@ pub enum Parameter {
     // MIDI parameters
     KeyValue,
     Velocity,
+    RelVelocity,
+    VoiceIndex,
     KeyAftertouch,
+    Timbre,
     Aftertouch,
     Pitchbend,
     ModWheel,
     SustainPedal,
+    Controller,
+    Follower,
+    OutputFollower,
 
     // System parameters
     Idle,
@@ -128,17 +183,53 @@ pub enum Parameter {
     Bpm,
     Allocation,
     PanOrigin,
+    Oversampling,
+    GlideTime,
+    GlideMode,
+    GlideCurve,
+    Stealing,
+    SameKey,
+    NotePriority,
+    Unison,
+    UnisonDetune,
+    UnisonSpread,
+    UnisonPhase,
 
     // Voice allocation types
     Ascending,
     RoundRobin,
     Random,
 
+    // Voice stealing strategies
+    Oldest,
+    Quietest,
+    Released,
+    KeepLowest,
+    KeepHighest,
+
+    // Note priority
+    LastNote,
+    LowNote,
+    HighNote,
+
+    // Same key behaviour
+    Stack,
+    Reuse,
+
     // Pan origin
     Center,
     Left,
     Right,
 
+    // Oversampling factors
+    X2,
+    X4,
+
+    // Glide modes and curves
+    Always,
+    ConstTime,
+    ConstRate,
+
     // Sync values
     Off,
     Whole,
@@ -317,15 +408,16 @@ impl MenuItem {
 }
 
 /* Top-level menu */
-pub static FUNCTIONS: [MenuItem; 8] = [
+pub static FUNCTIONS: [MenuItem; 9] = [
     MenuItem{item: Parameter::Oscillator, key: 'o', val_range: ValueRange::Int(1, NUM_OSCILLATORS as i64),  next: &OSC_PARAMS},
     MenuItem{item: Parameter::Envelope,   key: 'e', val_range: ValueRange::Int(1, NUM_ENVELOPES as i64),    next: &ENV_PARAMS},
     MenuItem{item: Parameter::Lfo,        key: 'l', val_range: ValueRange::Int(1, NUM_LFOS as i64),         next: &LFO_PARAMS},
-    MenuItem{item: Parameter::GlobalLfo,  key: 'g', val_range: ValueRange::Int(1, NUM_GLOBAL_LFOS as i64),  next: &LFO_PARAMS},
+    MenuItem{item: Parameter::GlobalLfo,  key: 'g', val_range: ValueRange::Int(1, NUM_GLOBAL_LFOS as i64),  next: &GLFO_PARAMS},
     MenuItem{item: Parameter::Filter,     key: 'f', val_range: ValueRange::Int(1, NUM_FILTERS as i64),      next: &FILTER_PARAMS},
     MenuItem{item: Parameter::Delay,      key: 'd', val_range: ValueRange::Int(1, 1),                       next: &DELAY_PARAMS},
     MenuItem{item: Parameter::Modulation, key: 'm', val_range: ValueRange::Int(1, NUM_MODULATORS as i64),   next: &MOD_PARAMS},
     MenuItem{item: Parameter::Patch,      key: 'p', val_range: ValueRange::Int(1, 1),                       next: &PATCH_PARAMS},
+    MenuItem{item: Parameter::Macro,      key: 'M', val_range: ValueRange::Int(1, NUM_MACROS as i64),       next: &MACRO_PARAMS},
 ];
 
 pub static OSC_PARAMS: [MenuItem; 11] = [
@@ -354,25 +446,49 @@ pub static OSC_TYPES: [MenuItem; 2] = [
     MenuItem{item: Parameter::Noise,     key: 'n', val_range: ValueRange::NoRange, next: &[]},
 ];
 
-pub static LFO_PARAMS: [MenuItem; 5] = [
+pub static LFO_PARAMS: [MenuItem; 12] = [
+    MenuItem{item: Parameter::Waveform,  key: 'w', val_range: ValueRange::Choice(&LFO_WAVEFORM), next: &[]},
+    MenuItem{item: Parameter::Frequency, key: 'f', val_range: ValueRange::Float(0.0, 44.1, 0.1), next: &[]},
+    MenuItem{item: Parameter::Sync,      key: 's', val_range: ValueRange::Choice(&SYNC_OPTIONS), next: &[]},
+    MenuItem{item: Parameter::Phase,     key: 'p', val_range: ValueRange::Float(0.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::Amount,    key: 'a', val_range: ValueRange::Float(0.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::Delay,     key: 'd', val_range: ValueRange::Float(0.0, 4000.0, 1.0), next: &[]}, // Value = Duration in ms
+    MenuItem{item: Parameter::FadeIn,    key: 'i', val_range: ValueRange::Float(0.0, 4000.0, 1.0), next: &[]},
+    MenuItem{item: Parameter::Retrigger, key: 'r', val_range: ValueRange::Choice(&LFO_RETRIGGER), next: &[]},
+    MenuItem{item: Parameter::NumSteps,  key: 'n', val_range: ValueRange::Int(1, MAX_LFO_STEPS as i64), next: &[]},
+    MenuItem{item: Parameter::EditStep,  key: 'e', val_range: ValueRange::Int(1, MAX_LFO_STEPS as i64), next: &[]}, // Selects the step edited by StepValue
+    MenuItem{item: Parameter::StepValue, key: 'v', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::Smoothing, key: 'm', val_range: ValueRange::Float(0.0, 1.0, 0.01), next: &[]},
+];
+
+/* Global LFOs are never triggered, so Delay, FadeIn and Retrigger don't apply */
+pub static GLFO_PARAMS: [MenuItem; 9] = [
     MenuItem{item: Parameter::Waveform,  key: 'w', val_range: ValueRange::Choice(&LFO_WAVEFORM), next: &[]},
     MenuItem{item: Parameter::Frequency, key: 'f', val_range: ValueRange::Float(0.0, 44.1, 0.1), next: &[]},
     MenuItem{item: Parameter::Sync,      key: 's', val_range: ValueRange::Choice(&SYNC_OPTIONS), next: &[]},
     MenuItem{item: Parameter::Phase,     key: 'p', val_range: ValueRange::Float(0.0, 1.0, 0.01), next: &[]},
     MenuItem{item: Parameter::Amount,    key: 'a', val_range: ValueRange::Float(0.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::NumSteps,  key: 'n', val_range: ValueRange::Int(1, MAX_LFO_STEPS as i64), next: &[]},
+    MenuItem{item: Parameter::EditStep,  key: 'e', val_range: ValueRange::Int(1, MAX_LFO_STEPS as i64), next: &[]}, // Selects the step edited by StepValue
+    MenuItem{item: Parameter::StepValue, key: 'v', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::Smoothing, key: 'm', val_range: ValueRange::Float(0.0, 1.0, 0.01), next: &[]},
 ];
 
-pub static FILTER_PARAMS: [MenuItem; 7] = [
+pub static FILTER_PARAMS: [MenuItem; 11] = [
     MenuItem{item: Parameter::Type,      key: 't', val_range: ValueRange::Choice(&FILTER_TYPE),    next: &[]},
     MenuItem{item: Parameter::Cutoff,    key: 'c', val_range: ValueRange::Float(1.0, 8000.0, 20.0), next: &[]},
     MenuItem{item: Parameter::Resonance, key: 'r', val_range: ValueRange::Float(0.0, 1.0, 0.01),   next: &[]},
     MenuItem{item: Parameter::Gain,      key: 'g', val_range: ValueRange::Float(0.0, 2.0, 0.01),   next: &[]},
     MenuItem{item: Parameter::Aux,       key: 'a', val_range: ValueRange::Float(0.0, 1.0, 0.01),   next: &[]},
-    MenuItem{item: Parameter::EnvDepth,  key: 'e', val_range: ValueRange::Float(0.0, 1.0, 0.01),   next: &[]},
-    MenuItem{item: Parameter::KeyFollow, key: 'k', val_range: ValueRange::Int(0, 1),               next: &[]},
+    MenuItem{item: Parameter::EnvDepth,  key: 'e', val_range: ValueRange::Float(-1.0, 1.0, 0.01),  next: &[]},
+    MenuItem{item: Parameter::EnvSource, key: 's', val_range: ValueRange::Int(1, NUM_ENVELOPES as i64), next: &[]},
+    MenuItem{item: Parameter::KeyFollow, key: 'k', val_range: ValueRange::Float(-100.0, 200.0, 1.0), next: &[]},
+    MenuItem{item: Parameter::KeyCenter, key: 'n', val_range: ValueRange::Int(0, 127),             next: &[]},
+    MenuItem{item: Parameter::FmSource,  key: 'o', val_range: ValueRange::Int(0, NUM_OSCILLATORS as i64), next: &[]},
+    MenuItem{item: Parameter::FmAmount,  key: 'm', val_range: ValueRange::Float(0.0, 1.0, 0.01),   next: &[]},
 ];
 
-pub static FILTER_TYPE: [MenuItem; 10] = [
+pub static FILTER_TYPE: [MenuItem; 11] = [
     MenuItem{item: Parameter::None,      key: 'n', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::SEM_LPF,   key: 's', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::SEM_BPF,   key: 'b', val_range: ValueRange::NoRange, next: &[]},
@@ -383,9 +499,10 @@ pub static FILTER_TYPE: [MenuItem; 10] = [
     MenuItem{item: Parameter::OM_LPF,    key: 'm', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::OM_BPF,    key: 'g', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::OM_HPF,    key: 'f', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::SEM_SVF,   key: 'v', val_range: ValueRange::NoRange, next: &[]},
 ];
 
-pub static ENV_PARAMS: [MenuItem; 8] = [
+pub static ENV_PARAMS: [MenuItem; 26] = [
     MenuItem{item: Parameter::Attack,     key: 'a', val_range: ValueRange::Float(1.0, 4000.0, 1.0), next: &[]}, // Value = Duration in ms
     MenuItem{item: Parameter::Decay,      key: 'd', val_range: ValueRange::Float(1.0, 4000.0, 1.0), next: &[]},
     MenuItem{item: Parameter::Sustain,    key: 's', val_range: ValueRange::Float(0.0, 1.0, 0.001),  next: &[]},
@@ -394,9 +511,27 @@ pub static ENV_PARAMS: [MenuItem; 8] = [
     MenuItem{item: Parameter::Delay,      key: 'e', val_range: ValueRange::Float(0.0, 4000.0, 1.0), next: &[]},
     MenuItem{item: Parameter::Loop,       key: 'l', val_range: ValueRange::Int(0, 1),               next: &[]},
     MenuItem{item: Parameter::ResetToZero,key: 'z', val_range: ValueRange::Int(0, 1),               next: &[]},
+    MenuItem{item: Parameter::Hold,       key: 'h', val_range: ValueRange::Float(0.0, 4000.0, 1.0), next: &[]},
+    MenuItem{item: Parameter::AttackCurve,key: 'c', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]}, // 0 = linear, > 0 exponential, < 0 logarithmic
+    MenuItem{item: Parameter::DecayCurve, key: 'y', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::ReleaseCurve,key: 'u', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::Mseg,       key: 'm', val_range: ValueRange::Int(0, 1),               next: &[]},
+    MenuItem{item: Parameter::NumPoints,  key: 'n', val_range: ValueRange::Int(0, MAX_ENV_POINTS as i64), next: &[]},
+    MenuItem{item: Parameter::EditPoint,  key: 'p', val_range: ValueRange::Int(1, MAX_ENV_POINTS as i64), next: &[]}, // Selects the point edited by PointTime/ Level/ Curve
+    MenuItem{item: Parameter::PointTime,  key: 'i', val_range: ValueRange::Float(0.0, 4000.0, 1.0), next: &[]},
+    MenuItem{item: Parameter::PointLevel, key: 'v', val_range: ValueRange::Float(0.0, 1.0, 0.001),  next: &[]},
+    MenuItem{item: Parameter::PointCurve, key: 'k', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::LoopStart,  key: 'b', val_range: ValueRange::Int(1, MAX_ENV_POINTS as i64), next: &[]},
+    MenuItem{item: Parameter::LoopEnd,    key: 'x', val_range: ValueRange::Int(1, MAX_ENV_POINTS as i64), next: &[]},
+    MenuItem{item: Parameter::AttackSync, key: 't', val_range: ValueRange::Choice(&SYNC_OPTIONS),  next: &[]}, // Overrides attack time if not Off
+    MenuItem{item: Parameter::DecaySync,  key: 'j', val_range: ValueRange::Choice(&SYNC_OPTIONS),  next: &[]},
+    MenuItem{item: Parameter::ReleaseSync,key: 'w', val_range: ValueRange::Choice(&SYNC_OPTIONS),  next: &[]},
+    MenuItem{item: Parameter::VelAttack,  key: 'g', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::VelLevel,   key: 'o', val_range: ValueRange::Float(0.0, 1.0, 0.01),  next: &[]},
+    MenuItem{item: Parameter::KeyDecay,   key: 'q', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]},
 ];
 
-pub static LFO_WAVEFORM: [MenuItem; 7] = [
+pub static LFO_WAVEFORM: [MenuItem; 8] = [
     MenuItem{item: Parameter::Sine,      key: 's', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::Triangle,  key: 't', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::Saw,       key: 'w', val_range: ValueRange::NoRange, next: &[]},
@@ -404,6 +539,13 @@ pub static LFO_WAVEFORM: [MenuItem; 7] = [
     MenuItem{item: Parameter::Square,    key: 'q', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::SampleHold,key: 'h', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::Noise ,    key: 'n', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Steps,     key: 'u', val_range: ValueRange::NoRange, next: &[]},
+];
+
+pub static LFO_RETRIGGER: [MenuItem; 3] = [
+    MenuItem{item: Parameter::Free,      key: 'f', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Note,      key: 'n', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::OneShot,   key: 'o', val_range: ValueRange::NoRange, next: &[]},
 ];
 
 pub static SYNC_OPTIONS: [MenuItem; 9] = [
@@ -432,14 +574,31 @@ pub static DELAY_TYPE: [MenuItem; 2] = [
     MenuItem{item: Parameter::PingPong,  key: 'p', val_range: ValueRange::NoRange, next: &[]},
 ];
 
-pub static MOD_PARAMS: [MenuItem; 4] = [
+pub static MOD_PARAMS: [MenuItem; 8] = [
     MenuItem{item: Parameter::Source,    key: 's', val_range: ValueRange::Func(&MOD_SOURCES),    next: &MOD_SOURCES},
     MenuItem{item: Parameter::Target,    key: 't', val_range: ValueRange::Param(&MOD_TARGETS),   next: &MOD_TARGETS},
     MenuItem{item: Parameter::Amount,    key: 'a', val_range: ValueRange::Float(-1.0, 1.0, 0.01),next: &[]},
     MenuItem{item: Parameter::Active,    key: 'v', val_range: ValueRange::Int(0, 1),             next: &[]},
+    MenuItem{item: Parameter::Curve,     key: 'c', val_range: ValueRange::Choice(&MOD_CURVES),   next: &[]},
+    MenuItem{item: Parameter::Polarity,  key: 'p', val_range: ValueRange::Choice(&MOD_POLARITY), next: &[]},
+    MenuItem{item: Parameter::Offset,    key: 'o', val_range: ValueRange::Float(-1.0, 1.0, 0.01),next: &[]},
+    MenuItem{item: Parameter::Via,       key: 'i', val_range: ValueRange::Func(&MOD_SOURCES),    next: &MOD_SOURCES}, // Scales the amount, None = off
+];
+
+pub static MOD_CURVES: [MenuItem; 4] = [
+    MenuItem{item: Parameter::Linear,      key: 'l', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Exponential, key: 'e', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::SCurve,      key: 's', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Stepped,     key: 't', val_range: ValueRange::NoRange, next: &[]},
 ];
 
-pub static PATCH_PARAMS: [MenuItem; 12] = [
+pub static MOD_POLARITY: [MenuItem; 3] = [
+    MenuItem{item: Parameter::Source,      key: 's', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Unipolar,    key: 'u', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Bipolar,     key: 'b', val_range: ValueRange::NoRange, next: &[]},
+];
+
+pub static PATCH_PARAMS: [MenuItem; 23] = [
     MenuItem{item: Parameter::Level,        key: 'l', val_range: ValueRange::Float(0.0, 100.0, 1.0),    next: &[]},
     MenuItem{item: Parameter::Drive,        key: 'd', val_range: ValueRange::Float(0.0, 10.0, 1.0),     next: &[]},
     MenuItem{item: Parameter::Pitchbend,    key: 'p', val_range: ValueRange::Int(0, 12),                next: &[]},
@@ -452,9 +611,24 @@ pub static PATCH_PARAMS: [MenuItem; 12] = [
     MenuItem{item: Parameter::Spread,       key: 's', val_range: ValueRange::Float(0.0, 1.0, 0.01),     next: &[]},
     MenuItem{item: Parameter::Allocation,   key: 'a', val_range: ValueRange::Choice(&VOICE_ALLOCATION), next: &[]},
     MenuItem{item: Parameter::PanOrigin,    key: 'o', val_range: ValueRange::Choice(&PAN_ORIGIN),       next: &[]},
+    MenuItem{item: Parameter::Oversampling, key: 'x', val_range: ValueRange::Choice(&OVERSAMPLING),     next: &[]},
+    MenuItem{item: Parameter::GlideTime,    key: 't', val_range: ValueRange::Float(0.0, 2000.0, 1.0),   next: &[]}, // Value = Duration in ms
+    MenuItem{item: Parameter::GlideMode,    key: 'g', val_range: ValueRange::Choice(&GLIDE_MODES),      next: &[]},
+    MenuItem{item: Parameter::GlideCurve,   key: 'c', val_range: ValueRange::Choice(&GLIDE_CURVES),     next: &[]},
+    MenuItem{item: Parameter::Stealing,     key: 'w', val_range: ValueRange::Choice(&VOICE_STEALING),   next: &[]},
+    MenuItem{item: Parameter::SameKey,      key: 'k', val_range: ValueRange::Choice(&SAME_KEY),         next: &[]},
+    MenuItem{item: Parameter::NotePriority, key: 'r', val_range: ValueRange::Choice(&NOTE_PRIORITY),    next: &[]},
+    MenuItem{item: Parameter::Unison,       key: 'u', val_range: ValueRange::Int(1, NUM_VOICES as i64), next: &[]},
+    MenuItem{item: Parameter::UnisonDetune, key: 'i', val_range: ValueRange::Float(0.0, 100.0, 1.0),    next: &[]}, // Value = Cents
+    MenuItem{item: Parameter::UnisonSpread, key: 'y', val_range: ValueRange::Float(0.0, 1.0, 0.01),     next: &[]},
+    MenuItem{item: Parameter::UnisonPhase,  key: 'h', val_range: ValueRange::Float(0.0, 1.0, 0.01),     next: &[]},
+];
+
+pub static MACRO_PARAMS: [MenuItem; 1] = [
+    MenuItem{item: Parameter::Level,        key: 'l', val_range: ValueRange::Float(0.0, 1.0, 0.01),     next: &[]},
 ];
 
-pub static MOD_SOURCES: [MenuItem; 9] = [
+pub static MOD_SOURCES: [MenuItem; 20] = [
     MenuItem{item: Parameter::Oscillator,  key: 'o', val_range: ValueRange::Int(1, NUM_OSCILLATORS as i64), next: &OSC_PARAMS},
     MenuItem{item: Parameter::Envelope,    key: 'e', val_range: ValueRange::Int(1, NUM_ENVELOPES as i64), next: &ENV_PARAMS},
     MenuItem{item: Parameter::Lfo,         key: 'l', val_range: ValueRange::Int(1, NUM_LFOS as i64), next: &LFO_PARAMS},
@@ -464,6 +638,17 @@ pub static MOD_SOURCES: [MenuItem; 9] = [
     MenuItem{item: Parameter::Pitchbend,   key: 'p', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
     MenuItem{item: Parameter::ModWheel,    key: 'm', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
     MenuItem{item: Parameter::SustainPedal,key: 's', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::KeyAftertouch,key: 'k', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::Timbre,      key: 't', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::KeyValue,    key: 'n', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::Random,      key: 'r', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::RelVelocity, key: 'u', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::VoiceIndex,  key: 'i', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::Controller,  key: 'c', val_range: ValueRange::Int(0, 127), next: &LFO_PARAMS}, // Function ID = controller number
+    MenuItem{item: Parameter::Follower,    key: 'f', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::OutputFollower, key: 'w', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::Macro,       key: 'M', val_range: ValueRange::Int(1, NUM_MACROS as i64), next: &LFO_PARAMS},
+    MenuItem{item: Parameter::None,        key: 'x', val_range: ValueRange::Int(1, 1), next: &LFO_PARAMS}, // No source, used to disable the via source
 ];
 
 pub static MOD_TARGETS: [MenuItem; 7] = [
@@ -476,8 +661,9 @@ pub static MOD_TARGETS: [MenuItem; 7] = [
     MenuItem{item: Parameter::Modulation, key: 'm', val_range: ValueRange::Int(1, NUM_MODULATORS as i64), next: &MOD_TARGET_PARAMS},
 ];
 
-pub static MOD_TARGET_PARAMS: [MenuItem; 2] = [
+pub static MOD_TARGET_PARAMS: [MenuItem; 3] = [
     MenuItem{item: Parameter::Amount,    key: 'a', val_range: ValueRange::Float(0.0, 1.0, 0.01), next: &[]},
+    MenuItem{item: Parameter::Offset,    key: 'o', val_range: ValueRange::Float(-1.0, 1.0, 0.01), next: &[]},
     MenuItem{item: Parameter::Active,    key: 'v', val_range: ValueRange::Int(0, 1),             next: &[]},
 ];
 
@@ -498,9 +684,44 @@ pub static VOICE_ALLOCATION: [MenuItem; 3] = [
     MenuItem{item: Parameter::Random,     key: 'o', val_range: ValueRange::NoRange, next: &[]},
 ];
 
+pub static GLIDE_MODES: [MenuItem; 2] = [
+    MenuItem{item: Parameter::Always, key: 'a', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Legato, key: 'l', val_range: ValueRange::NoRange, next: &[]},
+];
+
+pub static GLIDE_CURVES: [MenuItem; 2] = [
+    MenuItem{item: Parameter::ConstTime, key: 't', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::ConstRate, key: 'r', val_range: ValueRange::NoRange, next: &[]},
+];
+
+pub static VOICE_STEALING: [MenuItem; 5] = [
+    MenuItem{item: Parameter::Oldest,      key: 'o', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Quietest,    key: 'q', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Released,    key: 'r', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::KeepLowest,  key: 'l', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::KeepHighest, key: 'h', val_range: ValueRange::NoRange, next: &[]},
+];
+
+pub static NOTE_PRIORITY: [MenuItem; 3] = [
+    MenuItem{item: Parameter::LastNote, key: 'l', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::LowNote,  key: 'o', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::HighNote, key: 'h', val_range: ValueRange::NoRange, next: &[]},
+];
+
+pub static SAME_KEY: [MenuItem; 2] = [
+    MenuItem{item: Parameter::Stack, key: 's', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::Reuse, key: 'r', val_range: ValueRange::NoRange, next: &[]},
+];
+
 pub static PAN_ORIGIN: [MenuItem; 3] = [
     MenuItem{item: Parameter::Center, key: 'c', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::Left,   key: 'l', val_range: ValueRange::NoRange, next: &[]},
     MenuItem{item: Parameter::Right,  key: 'r', val_range: ValueRange::NoRange, next: &[]},
 ];
 
+pub static OVERSAMPLING: [MenuItem; 3] = [
+    MenuItem{item: Parameter::Off, key: 'o', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::X2,  key: '2', val_range: ValueRange::NoRange, next: &[]},
+    MenuItem{item: Parameter::X4,  key: '4', val_range: ValueRange::NoRange, next: &[]},
+];
+
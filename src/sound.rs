@@ -2,8 +2,8 @@ use super::DelayData;
 use super::EnvelopeData;
 use super::FilterData;
 use super::Float;
-use super::LfoData;
-use super::ModData;
+use super::{LfoData, LfoRetrigger};
+use super::{ModData, ModCurve, ModPolarity, mod_slots};
 use super::{OscData, OscType, OscRouting};
 use super::synth::*;
 use super::voice::*;
@@ -40,6 +40,33 @@ impl SyncValue {
             _ => panic!(),
         }
     }
+
+    /** Duration of the note value in ms at the given tempo.
+     *
+     * Returns None if sync is off or no tempo is set.
+     */
+    pub fn get_time(&self, bpm: Float) -> Option<Float> {
+        if bpm <= 0.0 {
+            return None;
+        }
+        let num_sixteenths = self.get_sixteenths()?;
+        Some(num_sixteenths * 1000.0 / ((bpm * 4.0) / 60.0))
+    }
+
+    /** Length of the note value in sixteenth notes, None if sync is off. */
+    pub fn get_sixteenths(&self) -> Option<Float> {
+        match self {
+            SyncValue::Off => None,
+            SyncValue::Whole => Some(16.0),
+            SyncValue::DottedHalf => Some(12.0),
+            SyncValue::Half => Some(8.0),
+            SyncValue::DottedQuarter => Some(6.0),
+            SyncValue::Quarter => Some(4.0),
+            SyncValue::DottedEigth => Some(3.0),
+            SyncValue::Eigth => Some(2.0),
+            SyncValue::Sixteenth => Some(1.0),
+        }
+    }
 }
 
 impl Default for SyncValue {
@@ -58,9 +85,12 @@ pub struct SoundData {
     pub filter: [FilterData; NUM_FILTERS],
     pub lfo: [LfoData; NUM_LFOS],
     pub glfo: [LfoData; NUM_GLOBAL_LFOS],
+    #[serde(with = "mod_slots")]
     pub modul: [ModData; NUM_MODULATORS],
     pub delay: DelayData,
     pub patch: PatchData,
+    #[serde(default)]
+    pub macros: [Float; NUM_MACROS],
 }
 
 impl Default for SoundData {
@@ -93,15 +123,11 @@ impl SoundData {
             LfoData{..Default::default()},
             LfoData{..Default::default()},
         ];
-        let modul = [
-            ModData::new(), ModData::new(), ModData::new(), ModData::new(),
-            ModData::new(), ModData::new(), ModData::new(), ModData::new(),
-            ModData::new(), ModData::new(), ModData::new(), ModData::new(),
-            ModData::new(), ModData::new(), ModData::new(), ModData::new(),
-        ];
+        let modul = [ModData::new(); NUM_MODULATORS];
         let delay = DelayData{..Default::default()};
         let patch = PatchData{..Default::default()};
-        SoundData{osc, env, filter, lfo, glfo, modul, delay, patch}
+        let macros = [0.0; NUM_MACROS];
+        SoundData{osc, env, filter, lfo, glfo, modul, delay, patch, macros}
     }
 
     pub fn init(&mut self) {
@@ -163,7 +189,11 @@ impl SoundData {
                     Parameter::Gain =>      { self.filter[id].gain = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     Parameter::Aux =>       { self.filter[id].aux = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     Parameter::EnvDepth =>  { self.filter[id].env_depth = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
-                    Parameter::KeyFollow => { self.filter[id].key_follow = if let ParameterValue::Int(x) = msg.value { x } else { panic!() }; }
+                    Parameter::KeyFollow => { self.filter[id].key_follow = if let ParameterValue::Float(x) = msg.value { x } else { panic!() } / 100.0; }
+                    Parameter::KeyCenter => { self.filter[id].key_center = if let ParameterValue::Int(x) = msg.value { x } else { panic!() }; }
+                    Parameter::EnvSource => { self.filter[id].env_source = if let ParameterValue::Int(x) = msg.value { x as usize } else { panic!() }; }
+                    Parameter::FmSource =>  { self.filter[id].fm_source = if let ParameterValue::Int(x) = msg.value { x } else { panic!() }; }
+                    Parameter::FmAmount =>  { self.filter[id].fm_amount = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     _ => {}
                 }
             }
@@ -176,6 +206,13 @@ impl SoundData {
                     Parameter::Sync =>      { lfo.sync = if let ParameterValue::Choice(x) = msg.value { SyncValue::from_int(x) } else { panic!() }; }
                     Parameter::Phase =>     { lfo.phase = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     Parameter::Amount =>    { lfo.amount = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::Delay =>     { lfo.delay = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::FadeIn =>    { lfo.fade_in = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::Retrigger => { lfo.retrigger = if let ParameterValue::Choice(x) = msg.value { LfoRetrigger::from_int(x) } else { panic!() }; }
+                    Parameter::NumSteps =>  { lfo.num_steps = if let ParameterValue::Int(x) = msg.value { x as usize } else { panic!() }; }
+                    Parameter::EditStep =>  { lfo.set_edit_step(if let ParameterValue::Int(x) = msg.value { x } else { panic!() }); }
+                    Parameter::StepValue => { let i = lfo.edit_step; lfo.steps[i] = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::Smoothing => { lfo.smoothing = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     _ => {}
                 }
             }
@@ -187,6 +224,13 @@ impl SoundData {
                     Parameter::Sync =>      { glfo.sync = if let ParameterValue::Choice(x) = msg.value { SyncValue::from_int(x) } else { panic!() }; }
                     Parameter::Phase =>     { glfo.phase = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     Parameter::Amount =>    { glfo.amount = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::Delay =>     { glfo.delay = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::FadeIn =>    { glfo.fade_in = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::Retrigger => { glfo.retrigger = if let ParameterValue::Choice(x) = msg.value { LfoRetrigger::from_int(x) } else { panic!() }; }
+                    Parameter::NumSteps =>  { glfo.num_steps = if let ParameterValue::Int(x) = msg.value { x as usize } else { panic!() }; }
+                    Parameter::EditStep =>  { glfo.set_edit_step(if let ParameterValue::Int(x) = msg.value { x } else { panic!() }); }
+                    Parameter::StepValue => { let i = glfo.edit_step; glfo.steps[i] = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::Smoothing => { glfo.smoothing = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     _ => {}
                 }
             }
@@ -200,6 +244,24 @@ impl SoundData {
                     Parameter::Delay =>       { self.env[id].delay = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     Parameter::Loop =>        { self.env[id].looping = if let ParameterValue::Int(x) = msg.value { x > 0 } else { panic!() }; }
                     Parameter::ResetToZero => { self.env[id].reset_to_zero = if let ParameterValue::Int(x) = msg.value { x > 0 } else { panic!() }; }
+                    Parameter::Hold =>        { self.env[id].hold = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::AttackCurve => { self.env[id].attack_curve = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::DecayCurve =>  { self.env[id].decay_curve = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::ReleaseCurve => { self.env[id].release_curve = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::Mseg =>        { self.env[id].mseg = if let ParameterValue::Int(x) = msg.value { x > 0 } else { panic!() }; }
+                    Parameter::NumPoints =>   { self.env[id].num_points = if let ParameterValue::Int(x) = msg.value { x as usize } else { panic!() }; }
+                    Parameter::EditPoint =>   { self.env[id].set_edit_point(if let ParameterValue::Int(x) = msg.value { x } else { panic!() }); }
+                    Parameter::PointTime =>   { let p = self.env[id].edit_point; self.env[id].points[p].time = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::PointLevel =>  { let p = self.env[id].edit_point; self.env[id].points[p].level = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::PointCurve =>  { let p = self.env[id].edit_point; self.env[id].points[p].curve = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::LoopStart =>   { self.env[id].loop_start = if let ParameterValue::Int(x) = msg.value { x as usize - 1 } else { panic!() }; }
+                    Parameter::LoopEnd =>     { self.env[id].loop_end = if let ParameterValue::Int(x) = msg.value { x as usize - 1 } else { panic!() }; }
+                    Parameter::AttackSync =>  { self.env[id].attack_sync = if let ParameterValue::Choice(x) = msg.value { SyncValue::from_int(x) } else { panic!() }; }
+                    Parameter::DecaySync =>   { self.env[id].decay_sync = if let ParameterValue::Choice(x) = msg.value { SyncValue::from_int(x) } else { panic!() }; }
+                    Parameter::ReleaseSync => { self.env[id].release_sync = if let ParameterValue::Choice(x) = msg.value { SyncValue::from_int(x) } else { panic!() }; }
+                    Parameter::VelAttack =>   { self.env[id].vel_attack = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::VelLevel =>    { self.env[id].vel_level = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::KeyDecay =>    { self.env[id].key_decay = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     _ => {}
                 }
             }
@@ -219,6 +281,10 @@ impl SoundData {
                     Parameter::Source => { if let ParameterValue::Function(x) = msg.value { self.modul[id].set_source(&x); } else { panic!() }; }
                     Parameter::Target => { if let ParameterValue::Param(x) = msg.value { self.modul[id].set_target(&x); } else { panic!() }; }
                     Parameter::Amount => { if let ParameterValue::Float(x) = msg.value { self.modul[id].set_amount(x) } else { panic!("{:?}", msg.value) }; }
+                    Parameter::Curve => { self.modul[id].curve = if let ParameterValue::Choice(x) = msg.value { ModCurve::from_int(x) } else { panic!() }; }
+                    Parameter::Polarity => { self.modul[id].polarity = if let ParameterValue::Choice(x) = msg.value { ModPolarity::from_int(x) } else { panic!() }; }
+                    Parameter::Offset => { self.modul[id].offset = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::Via => { if let ParameterValue::Function(x) = msg.value { self.modul[id].set_via(&x); } else { panic!() }; }
                     Parameter::Active => { self.modul[id].active = if let ParameterValue::Int(x) = msg.value { x > 0 } else { panic!() }; }
                     _ => {}
                 }
@@ -237,9 +303,23 @@ impl SoundData {
                     Parameter::Spread => { self.patch.voice_spread = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
                     Parameter::Allocation => { self.patch.voice_allocation = if let ParameterValue::Choice(x) = msg.value { VoiceAllocation::from_int(x) } else { panic!() }; }
                     Parameter::PanOrigin => { self.patch.pan_origin = if let ParameterValue::Choice(x) = msg.value { PanOrigin::from_int(x) } else { panic!() }; }
+                    Parameter::Oversampling => { self.patch.oversampling = if let ParameterValue::Choice(x) = msg.value { Oversampling::from_int(x) } else { panic!() }; }
+                    Parameter::GlideTime => { self.patch.glide_time = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::GlideMode => { self.patch.glide_mode = if let ParameterValue::Choice(x) = msg.value { GlideMode::from_int(x) } else { panic!() }; }
+                    Parameter::GlideCurve => { self.patch.glide_curve = if let ParameterValue::Choice(x) = msg.value { GlideCurve::from_int(x) } else { panic!() }; }
+                    Parameter::Unison => { self.patch.unison_voices = if let ParameterValue::Int(x) = msg.value { x as usize } else { panic!() }; }
+                    Parameter::UnisonDetune => { self.patch.unison_detune = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::UnisonSpread => { self.patch.unison_spread = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::UnisonPhase => { self.patch.unison_phase = if let ParameterValue::Float(x) = msg.value { x } else { panic!() }; }
+                    Parameter::NotePriority => { self.patch.note_priority = if let ParameterValue::Choice(x) = msg.value { NotePriority::from_int(x) } else { panic!() }; }
+                    Parameter::SameKey => { self.patch.same_key = if let ParameterValue::Choice(x) = msg.value { SameKey::from_int(x) } else { panic!() }; }
+                    Parameter::Stealing => { self.patch.voice_stealing = if let ParameterValue::Choice(x) = msg.value { VoiceStealing::from_int(x) } else { panic!() }; }
                     _ => {}
                 }
             }
+            Parameter::Macro if msg.parameter == Parameter::Level => {
+                self.macros[id] = if let ParameterValue::Float(x) = msg.value { x } else { panic!() };
+            }
             Parameter::System => {}
             _ => {}
         }
@@ -275,7 +355,11 @@ impl SoundData {
                     Parameter::Gain => ParameterValue::Float(filter.gain),
                     Parameter::Aux => ParameterValue::Float(filter.aux),
                     Parameter::EnvDepth => ParameterValue::Float(filter.env_depth),
-                    Parameter::KeyFollow => ParameterValue::Int(filter.key_follow),
+                    Parameter::KeyFollow => ParameterValue::Float(filter.key_follow * 100.0),
+                    Parameter::KeyCenter => ParameterValue::Int(filter.key_center),
+                    Parameter::EnvSource => ParameterValue::Int(filter.env_source as i64),
+                    Parameter::FmSource => ParameterValue::Int(filter.fm_source),
+                    Parameter::FmAmount => ParameterValue::Float(filter.fm_amount),
                     _ => {panic!();}
                 }
             }
@@ -288,6 +372,13 @@ impl SoundData {
                     Parameter::Sync => ParameterValue::Choice(lfo.sync as usize),
                     Parameter::Phase => ParameterValue::Float(lfo.phase),
                     Parameter::Amount => ParameterValue::Float(lfo.amount),
+                    Parameter::Delay => ParameterValue::Float(lfo.delay),
+                    Parameter::FadeIn => ParameterValue::Float(lfo.fade_in),
+                    Parameter::Retrigger => ParameterValue::Choice(lfo.retrigger.to_int()),
+                    Parameter::NumSteps => ParameterValue::Int(lfo.num_steps as i64),
+                    Parameter::EditStep => ParameterValue::Int(lfo.edit_step as i64 + 1),
+                    Parameter::StepValue => ParameterValue::Float(lfo.steps[lfo.edit_step]),
+                    Parameter::Smoothing => ParameterValue::Float(lfo.smoothing),
                     _ => {panic!();}
                 }
             }
@@ -299,6 +390,13 @@ impl SoundData {
                     Parameter::Sync => ParameterValue::Choice(glfo.sync as usize),
                     Parameter::Phase => ParameterValue::Float(glfo.phase),
                     Parameter::Amount => ParameterValue::Float(glfo.amount),
+                    Parameter::Delay => ParameterValue::Float(glfo.delay),
+                    Parameter::FadeIn => ParameterValue::Float(glfo.fade_in),
+                    Parameter::Retrigger => ParameterValue::Choice(glfo.retrigger.to_int()),
+                    Parameter::NumSteps => ParameterValue::Int(glfo.num_steps as i64),
+                    Parameter::EditStep => ParameterValue::Int(glfo.edit_step as i64 + 1),
+                    Parameter::StepValue => ParameterValue::Float(glfo.steps[glfo.edit_step]),
+                    Parameter::Smoothing => ParameterValue::Float(glfo.smoothing),
                     _ => {panic!();}
                 }
             }
@@ -313,6 +411,24 @@ impl SoundData {
                     Parameter::Delay => ParameterValue::Float(env.delay),
                     Parameter::Loop => ParameterValue::Int(if env.looping { 1 } else { 0 }),
                     Parameter::ResetToZero => ParameterValue::Int(if env.reset_to_zero { 1 } else { 0 }),
+                    Parameter::Hold => ParameterValue::Float(env.hold),
+                    Parameter::AttackCurve => ParameterValue::Float(env.attack_curve),
+                    Parameter::DecayCurve => ParameterValue::Float(env.decay_curve),
+                    Parameter::ReleaseCurve => ParameterValue::Float(env.release_curve),
+                    Parameter::Mseg => ParameterValue::Int(if env.mseg { 1 } else { 0 }),
+                    Parameter::NumPoints => ParameterValue::Int(env.num_points as i64),
+                    Parameter::EditPoint => ParameterValue::Int(env.edit_point as i64 + 1),
+                    Parameter::PointTime => ParameterValue::Float(env.points[env.edit_point].time),
+                    Parameter::PointLevel => ParameterValue::Float(env.points[env.edit_point].level),
+                    Parameter::PointCurve => ParameterValue::Float(env.points[env.edit_point].curve),
+                    Parameter::LoopStart => ParameterValue::Int(env.loop_start as i64 + 1),
+                    Parameter::LoopEnd => ParameterValue::Int(env.loop_end as i64 + 1),
+                    Parameter::AttackSync => ParameterValue::Choice(env.attack_sync as usize),
+                    Parameter::DecaySync => ParameterValue::Choice(env.decay_sync as usize),
+                    Parameter::ReleaseSync => ParameterValue::Choice(env.release_sync as usize),
+                    Parameter::VelAttack => ParameterValue::Float(env.vel_attack),
+                    Parameter::VelLevel => ParameterValue::Float(env.vel_level),
+                    Parameter::KeyDecay => ParameterValue::Float(env.key_decay),
                     _ => {panic!();}
                 }
             }
@@ -334,6 +450,10 @@ impl SoundData {
                     Parameter::Target => ParameterValue::Param(modul.get_target()),
                     Parameter::Amount => ParameterValue::Float(modul.amount),
                     Parameter::Active => ParameterValue::Int(if modul.active { 1 } else { 0 }),
+                    Parameter::Curve => ParameterValue::Choice(modul.curve.to_int()),
+                    Parameter::Polarity => ParameterValue::Choice(modul.polarity.to_int()),
+                    Parameter::Offset => ParameterValue::Float(modul.offset),
+                    Parameter::Via => ParameterValue::Function(modul.get_via()),
                     _ => {panic!();}
                 }
             }
@@ -351,6 +471,23 @@ impl SoundData {
                     Parameter::Spread => ParameterValue::Float(self.patch.voice_spread),
                     Parameter::Allocation => ParameterValue::Choice(self.patch.voice_allocation as usize),
                     Parameter::PanOrigin => ParameterValue::Choice(self.patch.pan_origin as usize),
+                    Parameter::Oversampling => ParameterValue::Choice(self.patch.oversampling as usize),
+                    Parameter::GlideTime => ParameterValue::Float(self.patch.glide_time),
+                    Parameter::GlideMode => ParameterValue::Choice(self.patch.glide_mode.to_int()),
+                    Parameter::GlideCurve => ParameterValue::Choice(self.patch.glide_curve.to_int()),
+                    Parameter::Stealing => ParameterValue::Choice(self.patch.voice_stealing.to_int()),
+                    Parameter::SameKey => ParameterValue::Choice(self.patch.same_key.to_int()),
+                    Parameter::NotePriority => ParameterValue::Choice(self.patch.note_priority.to_int()),
+                    Parameter::Unison => ParameterValue::Int(self.patch.unison_voices as i64),
+                    Parameter::UnisonDetune => ParameterValue::Float(self.patch.unison_detune),
+                    Parameter::UnisonSpread => ParameterValue::Float(self.patch.unison_spread),
+                    Parameter::UnisonPhase => ParameterValue::Float(self.patch.unison_phase),
+                    _ => {panic!();}
+                }
+            }
+            Parameter::Macro => {
+                match param.parameter {
+                    Parameter::Level => ParameterValue::Float(self.macros[id]),
                     _ => {panic!();}
                 }
             }
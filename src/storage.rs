@@ -1,4 +1,5 @@
 use super::SoundData;
+use super::NUM_MACROS;
 use super::WtInfo;
 
 use log::info;
@@ -8,6 +9,9 @@ use std::fs::File;
 use std::io::BufReader;
 use std::io::prelude::*;
 
+// First sound data version using the exponential filter envelope depth
+const EXP_ENV_DEPTH_VERSION: &str = "0.0.9";
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct SoundBankInfo {
     sound_data_version: String,
@@ -17,13 +21,23 @@ struct SoundBankInfo {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SoundPatch {
     pub name: String,
-    pub data: SoundData
+    pub data: SoundData,
+    #[serde(default = "SoundPatch::default_macro_names")]
+    pub macro_names: [String; NUM_MACROS],
 }
 
 impl SoundPatch {
     pub fn new() -> SoundPatch {
         Default::default()
     }
+
+    fn default_macro_names() -> [String; NUM_MACROS] {
+        let mut names: [String; NUM_MACROS] = Default::default();
+        for (i, name) in names.iter_mut().enumerate() {
+            *name = format!("Macro {}", i + 1);
+        }
+        names
+    }
 }
 
 impl Default for SoundPatch {
@@ -31,7 +45,8 @@ impl Default for SoundPatch {
         let name = "Init".to_string();
         let mut data = SoundData{..Default::default()};
         data.init();
-        SoundPatch{name, data}
+        let macro_names = SoundPatch::default_macro_names();
+        SoundPatch{name, data, macro_names}
     }
 }
 
@@ -59,7 +74,8 @@ impl SoundBank {
                 let mut serialized = String::new();
                 reader.read_to_string(&mut serialized)?;
                 let result: Result<SoundBank, serde_json::error::Error> = serde_json::from_str(&serialized);
-                if let Ok(data) = result {
+                if let Ok(mut data) = result {
+                    data.upgrade(&self.info.sound_data_version);
                     *self = data;
                 }
             }
@@ -68,6 +84,27 @@ impl SoundBank {
         Ok(())
     }
 
+    /** Adjust sounds saved by an older version so that they sound the same.
+     *
+     * Afterwards the bank is marked with the given current version.
+     */
+    fn upgrade(&mut self, current_version: &str) {
+        if SoundBank::is_older(&self.info.sound_data_version, EXP_ENV_DEPTH_VERSION) {
+            for sound in self.sounds.iter_mut() {
+                for filter in sound.data.filter.iter_mut() {
+                    filter.env_linear = true;
+                }
+            }
+        }
+        self.info.sound_data_version = current_version.to_string();
+    }
+
+    // Compare two version strings of the form "x.y.z"
+    fn is_older(version: &str, than: &str) -> bool {
+        let parse = |v: &str| -> Vec<u32> { v.split('.').map(|x| x.parse().unwrap_or(0)).collect() };
+        parse(version) < parse(than)
+    }
+
     pub fn save_bank(&self, filename: &str) -> std::io::Result<()> {
         let mut file = File::create(filename)?;
         let serialized = serde_json::to_string_pretty(&self).unwrap();
@@ -82,5 +119,40 @@ impl SoundBank {
     pub fn set_sound(&mut self, sound_index: usize, to_sound: &SoundPatch) {
         self.sounds[sound_index].name = to_sound.name.clone();
         self.sounds[sound_index].data = to_sound.data;
+        self.sounds[sound_index].macro_names = to_sound.macro_names.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_banks_keep_linear_env_depth() {
+        let mut bank = SoundBank::new("0.0.7", "0.0.1");
+        bank.upgrade("0.0.9");
+        assert!(bank.sounds[0].data.filter[0].env_linear);
+        assert_eq!(bank.info.sound_data_version, "0.0.9");
+
+        let mut bank = SoundBank::new("0.0.9", "0.0.1");
+        bank.upgrade("0.0.10");
+        assert!(!bank.sounds[0].data.filter[1].env_linear);
+        assert!(SoundBank::is_older("0.0.9", "0.0.10"));
+    }
+
+    #[test]
+    fn macro_names_are_saved() {
+        let mut patch = SoundPatch::new();
+        patch.macro_names[2] = "Brightness".to_string();
+        let serialized = serde_json::to_string(&patch).unwrap();
+        let loaded: SoundPatch = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(loaded.macro_names, patch.macro_names);
+
+        // Patches saved without macro names get the default names
+        let old = serialized.replace(&format!(",\"macro_names\":{}", serde_json::to_string(&patch.macro_names).unwrap()), "");
+        assert!(!old.contains("macro_names"));
+        let loaded: SoundPatch = serde_json::from_str(&old).unwrap();
+        assert_eq!(loaded.macro_names[0], "Macro 1");
+        assert_eq!(loaded.macro_names[2], "Macro 3");
     }
 }
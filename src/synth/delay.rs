@@ -125,21 +125,10 @@ impl Delay {
     }
 
     pub fn update_bpm(&mut self, data: &mut DelayData, bpm: Float) {
-        if data.sync == SyncValue::Off {
-            return;
-        }
-        let num_sixteenths = match data.sync {
-            SyncValue::Whole => 16.0,
-            SyncValue::DottedHalf => 12.0,
-            SyncValue::Half => 8.0,
-            SyncValue::DottedQuarter => 6.0,
-            SyncValue::Quarter => 4.0,
-            SyncValue::DottedEigth => 3.0,
-            SyncValue::Eigth => 2.0,
-            SyncValue::Sixteenth => 1.0,
-            SyncValue::Off => panic!(),
+        let time = match data.sync.get_time(bpm) {
+            Some(t) => t / 1000.0,
+            None => return,
         };
-        let time = num_sixteenths / ((bpm * 4.0) / 60.0);
         data.time = if time < 0.01 {
             0.01
         } else if time > 1.0 {
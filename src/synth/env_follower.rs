@@ -0,0 +1,68 @@
+//! Envelope follower for deriving modulation values from an audio signal.
+//!
+//! Tracks the peak level of the input with separate attack and release
+//! times, which gives a smooth control signal in the range 0.0 - 1.0.
+
+use super::Float;
+
+// Time constants of the follower in seconds
+const ATTACK_TIME: Float = 0.005;
+const RELEASE_TIME: Float = 0.15;
+
+pub struct EnvFollower {
+    attack_coeff: Float,
+    release_coeff: Float,
+    level: Float,
+}
+
+impl EnvFollower {
+    pub fn new(sample_rate: Float) -> Self {
+        let attack_coeff = EnvFollower::get_coeff(ATTACK_TIME, sample_rate);
+        let release_coeff = EnvFollower::get_coeff(RELEASE_TIME, sample_rate);
+        EnvFollower{attack_coeff, release_coeff, level: 0.0}
+    }
+
+    // Coefficient for reaching 1 - 1/e of a step in the given time.
+    fn get_coeff(time: Float, sample_rate: Float) -> Float {
+        (-1.0 / (time * sample_rate)).exp()
+    }
+
+    pub fn reset(&mut self) {
+        self.level = 0.0;
+    }
+
+    /** Feed the next input sample to the follower, returns the new level. */
+    pub fn process(&mut self, sample: Float) -> Float {
+        let input = sample.abs().min(1.0);
+        let coeff = if input > self.level { self.attack_coeff } else { self.release_coeff };
+        self.level = input + coeff * (self.level - input);
+        self.level
+    }
+
+    /** Current level, without processing a new sample. */
+    pub fn get_level(&self) -> Float {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_attack_faster_than_release() {
+        let sample_rate = 44100.0;
+        let mut follower = EnvFollower::new(sample_rate);
+        let num_samples = (ATTACK_TIME * sample_rate) as usize * 5;
+        for _ in 0..num_samples {
+            follower.process(-1.0);
+        }
+        assert!(follower.get_level() > 0.99);
+
+        // After the same time, the release has only just started
+        for _ in 0..num_samples {
+            follower.process(0.0);
+        }
+        assert!(follower.get_level() > 0.8);
+    }
+}
@@ -1,7 +1,28 @@
 use super::Float;
+use super::SyncValue;
 
 use serde::{Serialize, Deserialize};
 
+/// Maximum number of breakpoints of a multi-segment envelope
+pub const MAX_ENV_POINTS: usize = 8;
+
+// Steepness of segment curves at curve value +/-1.0
+const CURVE_STEEPNESS: Float = 5.0;
+
+// Time scaling at full velocity with velocity to attack amount 1.0, in octaves
+const VEL_TIME_RANGE: Float = 4.0;
+
+// Key at which key scaling doesn't change the decay time (C4)
+const KEY_SCALE_CENTER: Float = 60.0;
+
+/** Breakpoint of a multi-segment envelope. */
+#[derive(Serialize, Deserialize, Copy, Clone, Default, Debug)]
+pub struct EnvPoint {
+    pub time: Float,  // Duration of the segment leading to this point in ms
+    pub level: Float, // Level reached at the end of the segment
+    pub curve: Float, // Shape of the segment, 0.0 = linear, > 0 exponential, < 0 logarithmic
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug)]
 pub struct EnvelopeData {
     pub delay: Float,
@@ -12,6 +33,44 @@ pub struct EnvelopeData {
     pub factor: Float,
     pub looping: bool,
     pub reset_to_zero: bool,
+    #[serde(default)]
+    pub hold: Float,          // Time to stay at full level after attack in ms
+    #[serde(default)]
+    pub attack_curve: Float,
+    #[serde(default)]
+    pub decay_curve: Float,
+    #[serde(default)]
+    pub release_curve: Float,
+    #[serde(default)]
+    pub attack_sync: SyncValue, // Use note length instead of ms if not Off
+    #[serde(default)]
+    pub decay_sync: SyncValue,
+    #[serde(default)]
+    pub release_sync: SyncValue,
+    #[serde(skip)]
+    pub bpm: Float,           // Tempo for the synced stages, set by update_bpm()
+
+    // Velocity and key scaling
+    #[serde(default)]
+    pub vel_attack: Float,    // Velocity shortens (> 0) or lengthens (< 0) the attack
+    #[serde(default)]
+    pub vel_level: Float,     // Amount of velocity reducing the envelope level
+    #[serde(default)]
+    pub key_decay: Float,     // Higher keys shorten (> 0) or lengthen (< 0) the decay
+
+    // Multi-segment envelope
+    #[serde(default)]
+    pub mseg: bool,           // Use breakpoints instead of ADSR
+    #[serde(default)]
+    pub num_points: usize,
+    #[serde(default)]
+    pub points: [EnvPoint; MAX_ENV_POINTS],
+    #[serde(default)]
+    pub loop_start: usize,    // Index of the point the loop jumps back to
+    #[serde(default)]
+    pub loop_end: usize,      // Index of the sustain/ loop point
+    #[serde(skip)]
+    pub edit_point: usize,    // Index of the point currently edited in the UI, not saved
 }
 
 impl EnvelopeData {
@@ -24,6 +83,77 @@ impl EnvelopeData {
         self.factor = 1.0;
         self.looping = false;
         self.reset_to_zero = false;
+        self.hold = 0.0;
+        self.attack_curve = 0.0;
+        self.decay_curve = 0.0;
+        self.release_curve = 0.0;
+        self.attack_sync = SyncValue::Off;
+        self.decay_sync = SyncValue::Off;
+        self.release_sync = SyncValue::Off;
+        self.vel_attack = 0.0;
+        self.vel_level = 0.0;
+        self.key_decay = 0.0;
+        self.mseg = false;
+        self.num_points = 4;
+        self.points = [EnvPoint{..Default::default()}; MAX_ENV_POINTS];
+        self.points[0] = EnvPoint{time: 15.0, level: 1.0, curve: 0.0};
+        self.points[1] = EnvPoint{time: 100.0, level: 0.5, curve: 0.0};
+        self.points[2] = EnvPoint{time: 100.0, level: 0.8, curve: 0.0};
+        self.points[3] = EnvPoint{time: 100.0, level: 0.0, curve: 0.0};
+        self.loop_start = 1;
+        self.loop_end = 2;
+        self.edit_point = 0;
+    }
+
+    /** Select the breakpoint to edit, counting from 1. */
+    pub fn set_edit_point(&mut self, point: i64) {
+        self.edit_point = (point.max(1) as usize - 1).min(MAX_ENV_POINTS - 1);
+    }
+
+    /** Set the tempo used for the tempo-synced stages.
+     *
+     * The stored times are kept, so that they are used again when sync is
+     * turned off.
+     */
+    pub fn update_bpm(&mut self, bpm: Float) {
+        self.bpm = bpm;
+    }
+
+    /** Effective attack time in ms, including tempo sync. */
+    pub fn get_attack(&self) -> Float {
+        self.attack_sync.get_time(self.bpm).unwrap_or(self.attack)
+    }
+
+    /** Effective decay time in ms, including tempo sync. */
+    pub fn get_decay(&self) -> Float {
+        self.decay_sync.get_time(self.bpm).unwrap_or(self.decay)
+    }
+
+    /** Effective release time in ms, including tempo sync. */
+    pub fn get_release(&self) -> Float {
+        self.release_sync.get_time(self.bpm).unwrap_or(self.release)
+    }
+
+    /** Get the durations in ms before and after the key is released.
+     *
+     * Used to display the envelope shape, assuming the sustain phase takes
+     * 25% of the total duration.
+     */
+    pub fn get_duration(&self) -> (Float, Float) {
+        let (mut held, release) = if self.mseg {
+            let num_points = self.num_points.min(MAX_ENV_POINTS);
+            let held_points = (self.loop_end + 1).min(num_points);
+            let held: Float = self.points[..held_points].iter().map(|p| p.time).sum();
+            let release: Float = self.points[held_points..num_points].iter().map(|p| p.time).sum();
+            (held, if held_points < num_points { release } else { self.get_release() })
+        } else {
+            (self.get_attack() + self.hold + self.get_decay(), self.get_release())
+        };
+        held += self.delay;
+        if !self.looping {
+            held += (held + release) / 3.0;
+        }
+        (held, release)
     }
 }
 
@@ -32,9 +162,11 @@ pub enum EnvState {
     Idle,
     Delay,
     Attack,
+    Hold,
     Decay,
     Sustain,
     Release,
+    Segment, // Running a segment of a multi-segment envelope
 }
 
 #[derive(Debug)]
@@ -42,24 +174,40 @@ pub struct Envelope {
     sample_rate: Float,
     rate_mul: Float,
 
+    // Current ramp
+    start_time: i64,
     end_time: i64,
-    increment: Float,
+    start_value: Float,
+    target_value: Float,
+    curve: Float,
+
     last_update: i64,
     last_value: Float,
     is_held: bool,
     state: EnvState,
+    segment: usize, // Index of the target point in multi-segment mode
+
+    // Note that triggered the envelope, for velocity and key scaling
+    key: Float,
+    velocity: Float,
 }
 
 impl Envelope {
     pub fn new(sample_rate: Float) -> Envelope {
         Envelope{sample_rate,
                  rate_mul: sample_rate / 1000.0, // Samples per ms
-                 increment: 0.0,
+                 start_time: 0,
                  end_time: 0,
+                 start_value: 0.0,
+                 target_value: 0.0,
+                 curve: 0.0,
                  last_update: 0,
                  last_value: 0.0,
                  is_held: false,
                  state: EnvState::Idle,
+                 segment: 0,
+                 key: KEY_SCALE_CENTER,
+                 velocity: 1.0,
         }
     }
 
@@ -69,6 +217,12 @@ impl Envelope {
         self.state = EnvState::Idle;
     }
 
+    /** Set key and velocity (0.0 - 1.0) of the note for scaling the envelope. */
+    pub fn set_note(&mut self, key: u8, velocity: Float) {
+        self.key = key as Float;
+        self.velocity = velocity;
+    }
+
     pub fn trigger(&mut self, sample_time: i64, data: &EnvelopeData) {
         self.is_held = true;
         self.select_initial_state(sample_time, data);
@@ -76,6 +230,19 @@ impl Envelope {
 
     pub fn release(&mut self, sample_time: i64, data: &EnvelopeData) {
         self.is_held = false;
+        if data.mseg {
+            match self.state {
+                EnvState::Idle | EnvState::Release => return,
+                EnvState::Segment if self.segment > data.loop_end => return, // Already past the sustain point
+                _ => (),
+            }
+            // Continue with the segments after the sustain point, if any
+            if data.loop_end + 1 < data.num_points.min(MAX_ENV_POINTS) {
+                self.segment = data.loop_end + 1;
+                self.change_state(EnvState::Segment, sample_time, data);
+                return;
+            }
+        }
         match self.state {
             EnvState::Release => (), // Don't change to release twice
             _ => self.change_state(EnvState::Release, sample_time, data),
@@ -84,23 +251,32 @@ impl Envelope {
 
     pub fn get_sample(&mut self, sample_time: i64, data: &EnvelopeData) -> Float {
         if sample_time == self.last_update {
-            return self.last_value;
+            return self.get_output(data);
         }
         match self.state {
             EnvState::Idle => return 0.0,
             EnvState::Delay => {
                 if sample_time >= self.end_time {
-                    self.change_state(EnvState::Attack, sample_time, data);
+                    self.select_first_stage(sample_time, data);
                 }
             }
             EnvState::Attack => {
-                self.last_value += self.increment;
+                self.last_value = self.get_ramp_value(sample_time);
+                if sample_time >= self.end_time {
+                    if data.hold > 0.0 {
+                        self.change_state(EnvState::Hold, sample_time, data);
+                    } else {
+                        self.change_state(EnvState::Decay, sample_time, data);
+                    }
+                }
+            }
+            EnvState::Hold => {
                 if sample_time >= self.end_time {
                     self.change_state(EnvState::Decay, sample_time, data);
                 }
             }
             EnvState::Decay => {
-                self.last_value += self.increment;
+                self.last_value = self.get_ramp_value(sample_time);
                 if sample_time >= self.end_time {
                     if data.looping {
                         self.change_state(EnvState::Release, sample_time, data);
@@ -109,9 +285,16 @@ impl Envelope {
                     }
                 }
             }
-            EnvState::Sustain => self.last_value = data.sustain, // Might be updated while not is held,
+            EnvState::Sustain => {
+                // Might be updated while not is held
+                self.last_value = if data.mseg {
+                    data.points[self.segment].level
+                } else {
+                    data.sustain
+                };
+            }
             EnvState::Release => {
-                self.last_value += self.increment;
+                self.last_value = self.get_ramp_value(sample_time);
                 if sample_time >= self.end_time {
                     if self.is_held && data.looping {
                         self.select_initial_state(sample_time, data);
@@ -120,6 +303,12 @@ impl Envelope {
                     }
                 }
             }
+            EnvState::Segment => {
+                self.last_value = self.get_ramp_value(sample_time);
+                if sample_time >= self.end_time {
+                    self.select_next_segment(sample_time, data);
+                }
+            }
         }
         if self.last_value > 1.0 {
             self.last_value = 1.0;
@@ -127,13 +316,24 @@ impl Envelope {
             self.last_value = 0.0;
         }
         self.last_update = sample_time;
-        self.last_value.powf(data.factor)
+        self.get_output(data)
+    }
+
+    // Apply the output exponent and velocity scaling to the current value.
+    fn get_output(&self, data: &EnvelopeData) -> Float {
+        let level = 1.0 - data.vel_level * (1.0 - self.velocity);
+        self.last_value.powf(data.factor) * level
     }
 
     pub fn is_running(&self) -> bool {
         !matches!(self.state, EnvState::Idle)
     }
 
+    /** Level of the last calculated sample, without output scaling. */
+    pub fn get_level(&self) -> Float {
+        self.last_value
+    }
+
     fn change_state(&mut self, new_state: EnvState, sample_time: i64, data: &EnvelopeData) {
         match new_state {
             EnvState::Idle => self.last_value = 0.0,
@@ -148,18 +348,21 @@ impl Envelope {
 
                 // We have a fixed slope based on the attack time. Starting on
                 // a non-zero value will shorten the time to reach 1.0.
-                self.increment = self.calc_increment(0.0, 1.0, data.attack);
-
                 // If we're not starting at zero, time_frac will tell us how
                 // much faster we will reach the target.
                 let time_frac = 1.0 - self.last_value;
-                self.end_time = self.calc_end_time(sample_time, data.attack * time_frac);
+                let vel_scale = (-data.vel_attack * self.velocity * VEL_TIME_RANGE).exp2();
+                self.start_ramp(sample_time, 1.0, data.get_attack() * time_frac * vel_scale, data.attack_curve);
+            }
+            EnvState::Hold => {
+                self.last_value = 1.0;
+                self.end_time = self.calc_end_time(sample_time, data.hold);
             }
             EnvState::Decay => {
                 // Decay always starts after hitting 1.0. Slope is based on the
                 // decay time and sustain level.
-                self.increment = self.calc_increment(1.0, data.sustain, data.decay);
-                self.end_time = self.calc_end_time(sample_time, data.decay);
+                let key_scale = (-data.key_decay * (self.key - KEY_SCALE_CENTER) / 12.0).exp2();
+                self.start_ramp(sample_time, data.sustain, data.get_decay() * key_scale, data.decay_curve);
             }
             EnvState::Sustain => {
             }
@@ -169,8 +372,11 @@ impl Envelope {
                 // actual release time constant (it's a time, not a rate).
                 // TODO: Maybe change that, to keep it consistent with attack
                 //       and decay?
-                self.increment = self.calc_increment(self.last_value, 0.0, data.release);
-                self.end_time = self.calc_end_time(sample_time, data.release);
+                self.start_ramp(sample_time, 0.0, data.get_release(), data.release_curve);
+            }
+            EnvState::Segment => {
+                let point = &data.points[self.segment];
+                self.start_ramp(sample_time, point.level, point.time, point.curve);
             }
         }
         self.state = new_state;
@@ -180,12 +386,68 @@ impl Envelope {
         if data.delay > 0.0 {
             self.change_state(EnvState::Delay, sample_time, data);
         } else {
+            self.select_first_stage(sample_time, data);
+        }
+    }
+
+    // Start the first stage after the delay
+    fn select_first_stage(&mut self, sample_time: i64, data: &EnvelopeData) {
+        if !data.mseg {
             self.change_state(EnvState::Attack, sample_time, data);
+        } else if data.num_points > 0 {
+            if data.reset_to_zero {
+                self.last_value = 0.0;
+            }
+            self.segment = 0;
+            self.change_state(EnvState::Segment, sample_time, data);
+        } else {
+            self.change_state(EnvState::Idle, sample_time, data);
         }
     }
 
-    fn calc_increment(&self, from: Float, to: Float, duration: Float) -> Float {
-        (to - from) / (duration * self.rate_mul)
+    // Called when a segment of a multi-segment envelope has finished.
+    fn select_next_segment(&mut self, sample_time: i64, data: &EnvelopeData) {
+        if self.is_held && self.segment == data.loop_end {
+            if data.looping {
+                self.segment = data.loop_start.min(MAX_ENV_POINTS - 1);
+                self.change_state(EnvState::Segment, sample_time, data);
+            } else {
+                self.change_state(EnvState::Sustain, sample_time, data);
+            }
+        } else if self.segment + 1 < data.num_points.min(MAX_ENV_POINTS) {
+            self.segment += 1;
+            self.change_state(EnvState::Segment, sample_time, data);
+        } else {
+            self.change_state(EnvState::Idle, sample_time, data);
+        }
+    }
+
+    // Start a ramp from the current value to the target value.
+    fn start_ramp(&mut self, sample_time: i64, target: Float, duration: Float, curve: Float) {
+        self.start_time = sample_time;
+        self.end_time = self.calc_end_time(sample_time, duration);
+        self.start_value = self.last_value;
+        self.target_value = target;
+        self.curve = curve;
+    }
+
+    fn get_ramp_value(&self, sample_time: i64) -> Float {
+        let duration = self.end_time - self.start_time;
+        let progress = if duration > 0 {
+            ((sample_time - self.start_time) as Float / duration as Float).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        self.start_value + (self.target_value - self.start_value) * Envelope::shape(progress, self.curve)
+    }
+
+    // Bend the linear progress of a segment according to the curve value.
+    fn shape(progress: Float, curve: Float) -> Float {
+        if curve == 0.0 {
+            return progress;
+        }
+        let k = curve * CURVE_STEEPNESS;
+        ((k * progress).exp() - 1.0) / (k.exp() - 1.0)
     }
 
     fn calc_end_time(&self, sample_time: i64, end_time: Float) -> i64 {
@@ -200,7 +462,8 @@ impl Envelope {
 #[cfg(test)]
 mod tests {
 
-use super::{Envelope, EnvelopeData, EnvState};
+use super::{Envelope, EnvelopeData, EnvPoint, EnvState, MAX_ENV_POINTS};
+use super::SyncValue;
 use super::super::Float;
 
 struct TestContext {
@@ -239,6 +502,7 @@ impl TestContext {
                 factor: 1.0,
                 looping: false,
                 reset_to_zero: false,
+                ..Default::default()
             },
             last_time: 0,
         }
@@ -436,4 +700,172 @@ fn retrigger_in_decay() {
     assert_eq!(c.state(), EnvState::Decay);
 }
 
+#[test]
+fn hold_keeps_full_level_after_attack() {
+    let mut c = TestContext::new();
+    c.data.delay = 0.0;
+    c.data.hold = 10.0;
+    c.trigger(0);
+
+    assert!(close(c.get_sample(10), 1.0));
+    assert_eq!(c.state(), EnvState::Hold);
+    assert!(close(c.get_sample(15), 1.0));
+    assert_eq!(c.state(), EnvState::Hold);
+
+    // Decay starts after the hold time
+    assert!(close(c.get_sample(20), 1.0));
+    assert_eq!(c.state(), EnvState::Decay);
+    assert!(close(c.get_sample(25), 0.75));
+}
+
+#[test]
+fn curve_bends_segment() {
+    let mut c = TestContext::new();
+    c.data.delay = 0.0;
+    c.data.attack_curve = 1.0;
+    c.data.reset_to_zero = true;
+    c.trigger(0);
+    let exp = c.get_sample(5);
+
+    c.reset();
+    c.data.attack_curve = -1.0;
+    c.trigger(10);
+    let log = c.get_sample(15);
+
+    // Exponential starts slow, logarithmic starts fast
+    assert!(exp < 0.5);
+    assert!(log > 0.5);
+    assert!(close(exp, 1.0 - log));
+    assert!(close(c.get_sample(20), 1.0));
+}
+
+fn init_mseg(c: &mut TestContext) {
+    c.data.delay = 0.0;
+    c.data.mseg = true;
+    c.data.num_points = 4;
+    c.data.points[0] = EnvPoint{time: 10.0, level: 1.0, curve: 0.0};
+    c.data.points[1] = EnvPoint{time: 10.0, level: 0.5, curve: 0.0};
+    c.data.points[2] = EnvPoint{time: 10.0, level: 0.7, curve: 0.0};
+    c.data.points[3] = EnvPoint{time: 10.0, level: 0.0, curve: 0.0};
+    c.data.loop_start = 1;
+    c.data.loop_end = 2;
+}
+
+#[test]
+fn mseg_sustains_at_loop_end() {
+    let mut c = TestContext::new();
+    init_mseg(&mut c);
+    c.trigger(0);
+
+    assert!(close(c.get_sample(10), 1.0));
+    assert!(close(c.get_sample(15), 0.75));
+    assert!(close(c.get_sample(20), 0.5));
+    assert!(close(c.get_sample(30), 0.7));
+    assert_eq!(c.state(), EnvState::Sustain);
+    assert!(close(c.get_sample(50), 0.7));
+
+    // Release runs the segments after the loop end
+    c.release(50);
+    assert_eq!(c.state(), EnvState::Segment);
+    assert!(close(c.get_sample(55), 0.35));
+    assert!(close(c.get_sample(60), 0.0));
+    assert_eq!(c.state(), EnvState::Idle);
+}
+
+#[test]
+fn mseg_loops_while_held() {
+    let mut c = TestContext::new();
+    init_mseg(&mut c);
+    c.data.looping = true;
+    c.trigger(0);
+
+    assert!(close(c.get_sample(30), 0.7));
+    assert_eq!(c.state(), EnvState::Segment);
+
+    // Back to the loop start point
+    assert!(close(c.get_sample(40), 0.5));
+    assert!(close(c.get_sample(50), 0.7));
+    assert!(close(c.get_sample(60), 0.5));
+
+    c.release(60);
+    assert!(close(c.get_sample(70), 0.0));
+    assert_eq!(c.state(), EnvState::Idle);
+}
+
+#[test]
+fn synced_times_follow_tempo() {
+    let mut c = TestContext::new();
+    c.data.attack_sync = SyncValue::Quarter;
+    c.data.release_sync = SyncValue::Sixteenth;
+    c.data.update_bpm(120.0);
+    assert!(close(c.data.get_attack(), 500.0));
+    assert!(close(c.data.get_decay(), 10.0)); // Not synced
+    assert!(close(c.data.get_release(), 125.0));
+
+    c.data.update_bpm(60.0);
+    assert!(close(c.data.get_attack(), 1000.0));
+
+    // The stored time is used again when sync is turned off
+    let attack = c.data.attack;
+    c.data.attack_sync = SyncValue::Off;
+    assert!(close(c.data.get_attack(), attack));
+}
+
+#[test]
+fn velocity_scales_attack_and_level() {
+    let mut c = TestContext::new();
+    c.data.delay = 0.0;
+    c.data.vel_attack = 0.25; // One octave faster at full velocity
+    c.data.vel_level = 0.5;
+    c.env.set_note(60, 1.0);
+    c.trigger(0);
+    assert!(close(c.get_sample(5), 1.0));
+    assert_eq!(c.state(), EnvState::Decay);
+
+    c.reset();
+    c.data.reset_to_zero = true;
+    c.env.set_note(60, 0.0);
+    c.trigger(10);
+    assert!(close(c.get_sample(15), 0.25)); // Normal attack time, half level
+    assert!(close(c.get_sample(20), 0.5));
+}
+
+#[test]
+fn key_scales_decay() {
+    let mut c = TestContext::new();
+    c.data.delay = 0.0;
+    c.data.key_decay = 1.0;
+    c.env.set_note(72, 1.0); // One octave above center, half decay time
+    c.trigger(0);
+    assert!(close(c.get_sample(10), 1.0));
+    assert!(close(c.get_sample(15), 0.5));
+    assert_eq!(c.state(), EnvState::Sustain);
+}
+
+#[test]
+fn edit_point_is_clamped_and_not_saved() {
+    let mut data = EnvelopeData{..Default::default()};
+    data.init();
+    data.set_edit_point(0);
+    assert_eq!(data.edit_point, 0);
+    data.set_edit_point(MAX_ENV_POINTS as i64 + 1);
+    assert_eq!(data.edit_point, MAX_ENV_POINTS - 1);
+    let json = serde_json::to_string(&data).unwrap();
+    assert!(!json.contains("edit_point"));
+    let loaded: EnvelopeData = serde_json::from_str(&json.replace("\"mseg\"", "\"edit_point\":99,\"mseg\"")).unwrap();
+    assert_eq!(loaded.edit_point, 0);
+}
+
+#[test]
+fn repeated_read_returns_same_value() {
+    let mut c = TestContext::new();
+    c.data.factor = 2.0;
+    c.data.vel_level = 1.0;
+    c.env.set_note(60, 0.5);
+    c.trigger(0);
+    let first = c.get_sample(15);
+    assert!(close(first, 0.125));
+    assert_eq!(c.env.get_sample(15, &c.data), first);
+}
+
 } // mod test
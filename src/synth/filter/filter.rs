@@ -16,6 +16,7 @@ pub enum FilterType {
     HPF1, // 1-pole high pass filter
     HPF2, // 2-pole high pass filter
     HPF4, // 4-pole high pass filter
+    SVF2, // 2-pole state variable filter, morphing between LP, BP, HP and BS
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug)]
@@ -24,9 +25,19 @@ pub struct FilterData {
     pub cutoff: Float,
     pub resonance: Float,
     pub gain: Float,
-    pub aux: Float, // General purpose control, usage is filter dependent
-    pub env_depth: Float, // Depth of Envevlope 2 cutoff modulation
-    pub key_follow: i64,
+    pub aux: Float, // General purpose control, usage is filter dependent (SVF: morph LP-BP-HP-BS)
+    pub env_depth: Float, // Depth of envelope cutoff modulation, 1.0 = +ENV_RANGE octaves
+    #[serde(default = "FilterData::default_env_source")]
+    pub env_source: usize, // Envelope modulating the cutoff (1 - NUM_ENVELOPES)
+    pub key_follow: Float,  // Key tracking amount, 1.0 = 100%. Old patches store 0/1 here.
+    #[serde(default = "FilterData::default_key_center")]
+    pub key_center: i64,    // MIDI key at which key tracking doesn't change the cutoff
+    #[serde(default)]
+    pub fm_source: i64,     // Oscillator used for audio-rate cutoff modulation (0 = off)
+    #[serde(default)]
+    pub fm_amount: Float,   // Depth of the cutoff FM, 1.0 = +/- FM_RANGE octaves
+    #[serde(default)]
+    pub env_linear: bool,   // Scale the cutoff linearly by the envelope, set for patches older than 0.0.9
 } 
 
 impl FilterData {
@@ -37,11 +48,34 @@ impl FilterData {
         self.gain = 0.0;
         self.aux = 0.0;
         self.env_depth = 0.0;
-        self.key_follow = 0;
+        self.key_follow = 0.0;
+        self.key_center = FilterData::default_key_center();
+        self.env_source = FilterData::default_env_source();
+        self.fm_source = 0;
+        self.fm_amount = 0.0;
+        self.env_linear = false;
+    }
+
+    fn default_key_center() -> i64 {
+        69 // A4, 440 Hz
+    }
+
+    fn default_env_source() -> usize {
+        2 // Envelope 2 used to be hardwired to the filters
     }
 }
 
+// Cutoff range covered by audio-rate FM with amount 1.0, in octaves
+const FM_RANGE: Float = 4.0;
+
+// Cutoff range covered by the filter envelope with depth 1.0, in octaves
+const ENV_RANGE: Float = 8.0;
+
+// Maximum cutoff frequency relative to the sample rate
+const MAX_CUTOFF_FACTOR: Float = 0.45;
+
 pub struct Filter {
+    sample_rate: Float,
     last_cutoff: Float,
     last_resonance: Float,
 
@@ -54,12 +88,14 @@ pub struct Filter {
     om_lpf: OberMoog,
     om_bpf: OberMoog,
     om_hpf: OberMoog,
+    sem_svf: SEM,
 }
 
 impl Filter {
     pub fn new(sample_rate: u32) -> Filter {
         let sample_rate: Float = sample_rate as Float;
-        Filter{last_cutoff: 0.0,
+        Filter{sample_rate,
+               last_cutoff: 0.0,
                last_resonance: 0.0,
                sem_lpf: SEM::new(sample_rate, FilterType::LPF2),
                sem_bpf: SEM::new(sample_rate, FilterType::BPF2),
@@ -70,6 +106,7 @@ impl Filter {
                om_lpf: OberMoog::new(sample_rate, FilterType::LPF4),
                om_bpf: OberMoog::new(sample_rate, FilterType::BPF4),
                om_hpf: OberMoog::new(sample_rate, FilterType::HPF4),
+               sem_svf: SEM::new(sample_rate, FilterType::SVF2),
         }
     }
 
@@ -83,24 +120,58 @@ impl Filter {
         self.om_lpf.reset();
         self.om_bpf.reset();
         self.om_hpf.reset();
+        self.sem_svf.reset();
+    }
+
+    /** Change the sample rate the filter runs at, e.g. for oversampling. */
+    pub fn set_sample_rate(&mut self, sample_rate: Float) {
+        self.sample_rate = sample_rate;
+        self.sem_lpf.set_sample_rate(sample_rate);
+        self.sem_bpf.set_sample_rate(sample_rate);
+        self.sem_hpf.set_sample_rate(sample_rate);
+        self.sem_bsf.set_sample_rate(sample_rate);
+        self.k35_lpf.set_sample_rate(sample_rate);
+        self.k35_hpf.set_sample_rate(sample_rate);
+        self.om_lpf.set_sample_rate(sample_rate);
+        self.om_bpf.set_sample_rate(sample_rate);
+        self.om_hpf.set_sample_rate(sample_rate);
+        self.sem_svf.set_sample_rate(sample_rate);
+        self.last_cutoff = 0.0; // Force recalculation of coefficients
+        self.reset();
     }
 
-    pub fn process(&mut self, sample: Float, data: &mut FilterData, freq: Float, fmod: Float) -> Float {
+    /** Run a sample through the filter.
+     *
+     * freq is the frequency of the played note (for key follow), fmod the
+     * filter envelope output and fm_in the output of the oscillator selected
+     * as audio-rate cutoff modulation source.
+     */
+    pub fn process(&mut self, sample: Float, data: &mut FilterData, freq: Float, fmod: Float, fm_in: Float) -> Float {
 
         // Calculate effective cutoff frequency
         let mut cutoff = data.cutoff;
-        if data.key_follow == 1 {
-            cutoff = freq * (cutoff / 440.0);
-            if cutoff > 8000.0 {
-                cutoff = 8000.0;
-            } else if cutoff < 1.0 {
-                cutoff = 1.0;
+        if data.key_follow != 0.0 {
+            // Track the key relative to the center key, 1.0 = 1 octave per octave
+            let center_freq = 440.0 * ((data.key_center - 69) as Float / 12.0).exp2();
+            cutoff *= (freq / center_freq).powf(data.key_follow);
+            cutoff = cutoff.clamp(1.0, self.get_max_cutoff());
+        }
+
+        // Apply filter envelope (exponential, in octaves, negative depth inverts)
+        if data.env_linear {
+            // Old patches used the envelope output directly as cutoff factor
+            if data.env_depth > 0.0 {
+                cutoff *= fmod * data.env_depth;
             }
+        } else if data.env_depth != 0.0 {
+            cutoff *= (fmod * data.env_depth * ENV_RANGE).exp2();
+            cutoff = cutoff.clamp(1.0, self.get_max_cutoff());
         }
 
-        // Apply filter envelope
-        if data.env_depth > 0.0 {
-            cutoff *= fmod * data.env_depth;
+        // Apply audio-rate cutoff modulation (exponential, in octaves)
+        if data.fm_source > 0 && data.fm_amount > 0.0 {
+            cutoff *= (fm_in * data.fm_amount * FM_RANGE).exp2();
+            cutoff = cutoff.clamp(1.0, self.get_max_cutoff());
         }
 
         // If a parameter changed, update coefficients
@@ -120,6 +191,7 @@ impl Filter {
             7 => self.om_lpf.process(sample, data),
             8 => self.om_bpf.process(sample, data),
             9 => self.om_hpf.process(sample, data),
+            10 => self.sem_svf.process(sample, data),
             _ => panic!(),
         }
     }
@@ -137,16 +209,34 @@ impl Filter {
             7 => self.om_lpf.update(data, cutoff),
             8 => self.om_bpf.update(data, cutoff),
             9 => self.om_hpf.update(data, cutoff),
+            10 => self.sem_svf.update(data, cutoff),
             _ => panic!(),
         }
         self.last_resonance = data.resonance;
         self.last_cutoff = cutoff;
     }
 
+    // Highest cutoff frequency the filter can be set to
+    fn get_max_cutoff(&self) -> Float {
+        self.sample_rate * MAX_CUTOFF_FACTOR
+    }
+
     // ---------
     // Utilities
     // ---------
 
+    /** Calculate the prewarped integrator gain g = tan(pi * freq / sample_rate).
+     *
+     * Uses a rational approximation of tan(), which is cheap enough to
+     * recalculate the filter coefficients for every sample. The frequency is
+     * limited to 0.45 * sample_rate, where the approximation is still close.
+     */
+    pub fn prewarp(freq: Float, sample_rate: Float) -> Float {
+        let x = std::f64::consts::PI * freq.min(sample_rate * MAX_CUTOFF_FACTOR) / sample_rate;
+        let xx = x * x;
+        x * (15.0 - xx) / (15.0 - 6.0 * xx)
+    }
+
     /*
      * These functions were used in the old filter models.
 
@@ -173,3 +263,89 @@ impl Filter {
     }
     */
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_key_follow_values_are_migrated() {
+        let old = r#"{"filter_type":1,"cutoff":3000.0,"resonance":0.0,"gain":0.0,"aux":0.0,"env_depth":0.0,"key_follow":1}"#;
+        let data: FilterData = serde_json::from_str(old).unwrap();
+        assert_eq!(data.key_follow, 1.0);
+        assert_eq!(data.key_center, 69);
+    }
+
+    #[test]
+    fn key_follow_tracks_relative_to_center_key() {
+        let mut filter = Filter::new(44100);
+        let mut data = FilterData{..Default::default()};
+        data.init();
+        data.cutoff = 1000.0;
+        data.key_follow = 1.0;
+        filter.process(0.0, &mut data, 880.0, 0.0, 0.0);
+        assert!((filter.last_cutoff - 2000.0).abs() < 0.001);
+
+        data.key_follow = -1.0;
+        filter.process(0.0, &mut data, 880.0, 0.0, 0.0);
+        assert!((filter.last_cutoff - 500.0).abs() < 0.001);
+
+        data.key_center = 81; // One octave above A4
+        data.key_follow = 2.0;
+        filter.process(0.0, &mut data, 440.0, 0.0, 0.0);
+        assert!((filter.last_cutoff - 250.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn prewarp_is_close_to_tan() {
+        let sample_rate = 44100.0;
+        let mut freq = 20.0;
+        while freq <= sample_rate * MAX_CUTOFF_FACTOR {
+            let g = Filter::prewarp(freq, sample_rate);
+            let exact = (std::f64::consts::PI * freq / sample_rate).tan();
+            assert!((g / exact - 1.0).abs() < 0.035);
+
+            // Compare the cutoff tuned by the approximation to the exact one,
+            // in cents
+            let tuned = g.atan() * sample_rate / std::f64::consts::PI;
+            let cents = (freq / tuned).log2().abs() * 1200.0;
+            let limit = if freq <= 10000.0 { 0.2 } else { 7.0 };
+            assert!(cents < limit, "{} Hz is off by {} cents", freq, cents);
+            freq *= 1.01;
+        }
+    }
+
+    #[test]
+    fn env_depth_is_bipolar() {
+        let mut filter = Filter::new(44100);
+        let mut data = FilterData{..Default::default()};
+        data.init();
+        data.cutoff = 1000.0;
+        data.env_depth = 0.125; // One octave
+        filter.process(0.0, &mut data, 440.0, 1.0, 0.0);
+        assert!((filter.last_cutoff - 2000.0).abs() < 0.001);
+
+        data.env_depth = -0.125;
+        filter.process(0.0, &mut data, 440.0, 1.0, 0.0);
+        assert!((filter.last_cutoff - 500.0).abs() < 0.001);
+
+        filter.process(0.0, &mut data, 440.0, 0.0, 0.0);
+        assert!((filter.last_cutoff - 1000.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn linear_env_depth_is_kept_for_old_patches() {
+        let mut filter = Filter::new(44100);
+        let mut data = FilterData{..Default::default()};
+        data.init();
+        data.cutoff = 1000.0;
+        data.env_depth = 0.5;
+        data.env_linear = true;
+        filter.process(0.0, &mut data, 440.0, 1.0, 0.0);
+        assert!((filter.last_cutoff - 500.0).abs() < 0.001);
+
+        data.env_depth = -0.5;
+        filter.process(0.0, &mut data, 440.0, 1.0, 0.0);
+        assert!((filter.last_cutoff - 1000.0).abs() < 0.001);
+    }
+}
@@ -2,7 +2,7 @@
 //! "Designing Software Synthesizer Plug-Ins in C++" by Will Pirkle.
 
 use crate::Float;
-use super::{FilterData, FilterType, VAOnePole};
+use super::{Filter, FilterData, FilterType, VAOnePole};
 
 /// Sallen-Key filter as used in the Korg35
 pub struct K35 {
@@ -30,6 +30,10 @@ impl K35 {
         }
     }
 
+    pub fn set_sample_rate(&mut self, sample_rate: Float) {
+        self.sample_rate = sample_rate;
+    }
+
     pub fn reset(&mut self) {
         self.lpf1.reset();
         self.lpf2.reset();
@@ -41,10 +45,7 @@ impl K35 {
         // Map resonance from [0.0, 1.0] to the range [0.01, 2.0]
         self.k = (2.0 - 0.01) * data.resonance + 0.01;
 
-        let wd = (std::f64::consts::PI * 2.0) * freq;
-        let t = 1.0 / self.sample_rate;
-        let wa = (2.0 / t) * (wd * t / 2.0).tan();
-        let g = wa * t / 2.0;
+        let g = Filter::prewarp(freq, self.sample_rate);
         let gg = g / (1.0 + g);
         self.lpf1.set_alpha(gg);
         self.lpf2.set_alpha(gg);
@@ -2,7 +2,7 @@
 //! the book "Designing Software Synthesizer Plug-Ins in C++" by Will Pirkle.
 
 use crate::Float;
-use super::{FilterData, FilterType};
+use super::{Filter, FilterData, FilterType};
 use super::VAOnePole;
 
 pub struct OberMoog {
@@ -37,6 +37,10 @@ impl OberMoog {
         }
     }
 
+    pub fn set_sample_rate(&mut self, sample_rate: Float) {
+        self.sample_rate = sample_rate;
+    }
+
     pub fn reset(&mut self) {
         self.lpf1.reset();
         self.lpf2.reset();
@@ -49,10 +53,7 @@ impl OberMoog {
         self.k = 4.0 * data.resonance;
 
         // prewarp for BZT
-        let wd = 2.0 * std::f64::consts::PI * freq;
-        let t = 1.0 / self.sample_rate;
-        let wa = (2.0 / t) * (wd * t / 2.0).tan();
-        let g = wa * t / 2.0;
+        let g = Filter::prewarp(freq, self.sample_rate);
 
         // Feedforward coeff
         let gg = g / (1.0 + g);
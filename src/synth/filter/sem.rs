@@ -1,5 +1,5 @@
 use crate::Float;
-use super::{FilterData, FilterType};
+use super::{Filter, FilterData, FilterType};
 
 // One pole filter used to construct Oberheim Moog ladder filter
 pub struct SEM {
@@ -28,6 +28,10 @@ impl SEM {
         }
     }
 
+    pub fn set_sample_rate(&mut self, sample_rate: Float) {
+        self.sample_rate = sample_rate;
+    }
+
     pub fn reset(&mut self) {
         self.resonance = 0.5;
         self.alpha = 1.0;
@@ -41,10 +45,7 @@ impl SEM {
         // Map resonance from [0.0, 1.0] to the range [0.5, 25]
         self.resonance = (25.0 - 0.5) * data.resonance + 0.5;
 
-        let wd = (std::f64::consts::PI * 2.0) * freq;
-        let t = 1.0 / self.sample_rate;
-        let wa = (2.0 / t) * (wd * t / 2.0).tan();
-        let g = wa * t / 2.0;
+        let g = Filter::prewarp(freq, self.sample_rate);
         let r = 1.0 / (2.0 * self.resonance);
 
         self.alpha0 = 1.0 / (1.0 + (2.0 * r * g) + (g * g));
@@ -59,20 +60,40 @@ impl SEM {
             bpf = (bpf + data.gain).tanh();
         }
         let lpf = self.alpha * bpf + self.z12;
-        let _sem_bsf = data.aux * hpf + (1.0 - data.aux) * lpf; // TODO: Lost something here when translating
         self.z11 = self.alpha * hpf + bpf;
         self.z12 = self.alpha * bpf + lpf;
         match self.filter_type {
             FilterType::LPF2 => lpf,
             FilterType::BPF2 => bpf,
             FilterType::HPF2 => hpf,
-            FilterType::BSF2 => {
-                let r = 1.0 / (2.0 * self.resonance);
-                s - 2.0 * r * bpf
+            FilterType::BSF2 => self.get_bsf(s, bpf),
+            FilterType::SVF2 => {
+                let bsf = self.get_bsf(s, bpf);
+                SEM::morph(data.aux, lpf, bpf, hpf, bsf)
             }
             _ => panic!(),
         }
     }
+
+    fn get_bsf(&self, s: Float, bpf: Float) -> Float {
+        let r = 1.0 / (2.0 * self.resonance);
+        s - 2.0 * r * bpf
+    }
+
+    // Crossfade between the filter outputs.
+    //
+    // The range [0.0, 1.0] of the morph value is split into three sections,
+    // blending LP -> BP, BP -> HP and HP -> BS.
+    fn morph(value: Float, lpf: Float, bpf: Float, hpf: Float, bsf: Float) -> Float {
+        let pos = value.clamp(0.0, 1.0) * 3.0;
+        if pos < 1.0 {
+            lpf + (bpf - lpf) * pos
+        } else if pos < 2.0 {
+            bpf + (hpf - bpf) * (pos - 1.0)
+        } else {
+            hpf + (bsf - hpf) * (pos - 2.0)
+        }
+    }
 }
 
 
@@ -4,6 +4,9 @@ use super::{Float, SyncValue};
 
 use serde::{Serialize, Deserialize};
 
+/// Maximum number of steps of a user-defined LFO wave
+pub const MAX_LFO_STEPS: usize = 16;
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 pub enum LfoWaveform {
     Sine,
@@ -13,12 +16,44 @@ pub enum LfoWaveform {
     Square,
     SnH,
     Noise,
+    Steps, // User-defined table of steps
 }
 
 impl Default for LfoWaveform {
     fn default() -> Self { LfoWaveform::Sine }
 }
 
+/** Behaviour of a voice LFO when a note is triggered. */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LfoRetrigger {
+    Free,    // Keep running, only restart delay and fade-in
+    Note,    // Restart at the start phase
+    OneShot, // Restart and stop after a single cycle
+}
+
+impl LfoRetrigger {
+    pub fn from_int(param: usize) -> LfoRetrigger {
+        match param {
+            0 => LfoRetrigger::Free,
+            1 => LfoRetrigger::Note,
+            2 => LfoRetrigger::OneShot,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            LfoRetrigger::Free => 0,
+            LfoRetrigger::Note => 1,
+            LfoRetrigger::OneShot => 2,
+        }
+    }
+}
+
+impl Default for LfoRetrigger {
+    fn default() -> Self { LfoRetrigger::Note }
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
 pub struct LfoData {
     pub waveform: LfoWaveform,
@@ -26,6 +61,22 @@ pub struct LfoData {
     pub sync: SyncValue,
     pub phase: Float,
     pub amount: Float,
+    #[serde(default)]
+    pub delay: Float,   // Time in ms before the LFO starts after a note
+    #[serde(default)]
+    pub fade_in: Float, // Time in ms to reach full amount after the delay
+    #[serde(default)]
+    pub retrigger: LfoRetrigger,
+    #[serde(default)]
+    pub num_steps: usize,
+    #[serde(default)]
+    pub steps: [Float; MAX_LFO_STEPS], // Step values from -1.0 to 1.0
+    #[serde(default)]
+    pub smoothing: Float, // Part of each step used to glide to the next one
+    #[serde(skip)]
+    pub bpm: Float, // Tempo for the synced frequency, set by update_bpm()
+    #[serde(skip)]
+    pub edit_step: usize, // Index of the step currently edited in the UI, not saved
 }
 
 impl LfoData {
@@ -34,6 +85,51 @@ impl LfoData {
         self.frequency = 1.0;
         self.phase = 0.0;
         self.amount = 1.0;
+        self.delay = 0.0;
+        self.fade_in = 0.0;
+        self.retrigger = LfoRetrigger::Note;
+        self.num_steps = 8;
+        for (i, step) in self.steps.iter_mut().enumerate() {
+            *step = if i < self.num_steps { -1.0 + 2.0 * i as Float / (self.num_steps - 1) as Float } else { 0.0 };
+        }
+        self.smoothing = 0.0;
+        self.edit_step = 0;
+    }
+
+    /** Select the step to edit, counting from 1. */
+    pub fn set_edit_step(&mut self, step: i64) {
+        self.edit_step = (step.max(1) as usize - 1).min(MAX_LFO_STEPS - 1);
+    }
+
+    /** Set the tempo used for the synced frequency.
+     *
+     * The stored frequency is kept, so that it is used again when sync is
+     * turned off.
+     */
+    pub fn update_bpm(&mut self, bpm: Float) {
+        self.bpm = bpm;
+    }
+
+    /** Effective frequency in Hz, the synced note length if sync is on. */
+    pub fn get_frequency(&self) -> Float {
+        match self.sync.get_time(self.bpm) {
+            Some(t) => 1000.0 / (t * self.get_cycle_factor()),
+            None => self.frequency,
+        }
+    }
+
+    /** Length of one wave cycle in sixteenth notes, None if sync is off. */
+    pub fn get_cycle_sixteenths(&self) -> Option<Float> {
+        self.sync.get_sixteenths().map(|s| s * self.get_cycle_factor())
+    }
+
+    // With the step wave, sync sets the length of a single step instead of
+    // the whole cycle.
+    fn get_cycle_factor(&self) -> Float {
+        match self.waveform {
+            LfoWaveform::Steps => self.num_steps.max(1) as Float,
+            _ => 1.0,
+        }
     }
 
     pub fn select_wave(&mut self, value: usize) {
@@ -45,6 +141,7 @@ impl LfoData {
             4 => LfoWaveform::Square,
             5 => LfoWaveform::SnH,
             6 => LfoWaveform::Noise,
+            7 => LfoWaveform::Steps,
             _ => panic!(),
         }
     }
@@ -58,6 +155,7 @@ impl LfoData {
             LfoWaveform::Square => 4,
             LfoWaveform::SnH => 5,
             LfoWaveform::Noise => 6,
+            LfoWaveform::Steps => 7,
         }
     }
 }
@@ -67,6 +165,8 @@ pub struct Lfo {
     last_update: i64, // Time of last sample
     position: Float, // Position in the wave at last update, going from 0.0 to 1.0
     last_value: Float, // Value of previous iteration (only used for S&H)
+    trigger_time: Option<i64>, // Time of last note trigger, None for free-running global LFOs
+    finished: bool, // One-shot cycle has completed
 }
 
 impl Lfo {
@@ -74,7 +174,7 @@ impl Lfo {
         let last_update = 0;
         let position = 0.0;
         let last_value = 0.0;
-        Lfo{sample_rate, last_update, position, last_value}
+        Lfo{sample_rate, last_update, position, last_value, trigger_time: None, finished: false}
     }
 
     fn get_sample_sine(&mut self) -> Float {
@@ -118,6 +218,23 @@ impl Lfo {
         (rand::random::<Float>() * 2.0) - 1.0
     }
 
+    fn get_sample_steps(&mut self, data: &LfoData) -> Float {
+        let num_steps = data.num_steps.clamp(1, MAX_LFO_STEPS);
+        let pos = self.position.min(1.0) * num_steps as Float;
+        let index = (pos as usize).min(num_steps - 1);
+        let value = data.steps[index];
+        let smooth_start = 1.0 - data.smoothing;
+        let frac = pos - index as Float;
+        if data.smoothing > 0.0 && frac > smooth_start {
+            // Glide linearly to the next step at the end of this one
+            let next = data.steps[(index + 1) % num_steps];
+            let t = (frac - smooth_start) / data.smoothing;
+            value + (next - value) * t
+        } else {
+            value
+        }
+    }
+
     fn get_sample_snh(&mut self, get_new_value: bool) -> Float {
         if get_new_value {
             self.last_value = (rand::random::<Float>() * 2.0) - 1.0;
@@ -135,13 +252,22 @@ impl Lfo {
             complete = true;
         }
 
-        let freq_speed = data.frequency / self.sample_rate as Float;
-        let diff = freq_speed * dt_f;
-        self.position += diff;
+        let gain = self.get_gain(sample_clock, data);
+        if (gain > 0.0 || data.retrigger == LfoRetrigger::Free) && !self.finished {
+            // The LFO is stopped during the delay time, unless it is free running
+            let freq_speed = data.get_frequency() / self.sample_rate as Float;
+            let diff = freq_speed * dt_f;
+            self.position += diff;
+        }
         if self.position > 1.0 {
             // Completed one wave cycle
-            self.position -= 1.0;
             complete = true;
+            if data.retrigger == LfoRetrigger::OneShot && self.trigger_time.is_some() {
+                self.position = 1.0;
+                self.finished = true;
+            } else {
+                self.position -= 1.0;
+            }
         }
 
         result = match data.waveform {
@@ -152,7 +278,8 @@ impl Lfo {
             LfoWaveform::Square => self.get_sample_square(0.5),
             LfoWaveform::Noise => self.get_sample_noise(),
             LfoWaveform::SnH => self.get_sample_snh(complete),
-        } * data.amount;
+            LfoWaveform::Steps => self.get_sample_steps(data),
+        } * data.amount * gain;
 
         self.last_update += dt;
         if result > 1.0 {
@@ -161,8 +288,190 @@ impl Lfo {
         (result, complete)
     }
 
+    /** Start the delay and fade-in on a new note.
+     *
+     * Depending on the retrigger mode, the wave also restarts at the start
+     * phase.
+     */
+    pub fn trigger(&mut self, sample_clock: i64, data: &LfoData) {
+        self.trigger_time = Some(sample_clock);
+        self.finished = false;
+        if data.retrigger != LfoRetrigger::Free {
+            self.reset(sample_clock, data.phase);
+        }
+    }
+
+    // Amount of the output after delay and fade-in, from 0.0 to 1.0.
+    fn get_gain(&self, sample_clock: i64, data: &LfoData) -> Float {
+        let trigger_time = match self.trigger_time {
+            Some(t) => t,
+            None => return 1.0,
+        };
+        let elapsed = (sample_clock - trigger_time) as Float * 1000.0 / self.sample_rate as Float;
+        if elapsed < data.delay {
+            0.0
+        } else if elapsed < data.delay + data.fade_in {
+            (elapsed - data.delay) / data.fade_in
+        } else {
+            1.0
+        }
+    }
+
     pub fn reset(&mut self, sample_clock: i64, phase: Float) {
         self.last_update = sample_clock;
         self.position = phase;
     }
+
+    /** Move the phase part of the way towards the target phase.
+     *
+     * The distance is taken the short way around the cycle, so that the LFO
+     * never jumps by more than half a cycle.
+     */
+    pub fn nudge_phase(&mut self, target: Float, amount: Float) {
+        let mut diff = target - self.position;
+        if diff > 0.5 {
+            diff -= 1.0;
+        } else if diff < -0.5 {
+            diff += 1.0;
+        }
+        self.position += diff * amount;
+        if self.position < 0.0 {
+            self.position += 1.0;
+        } else if self.position > 1.0 {
+            self.position -= 1.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_data() -> LfoData {
+        let mut data = LfoData{..Default::default()};
+        data.init();
+        data.select_wave(2); // Saw up
+        data
+    }
+
+    #[test]
+    fn delay_and_fade_in_scale_output() {
+        let mut data = get_data();
+        data.delay = 10.0;
+        data.fade_in = 10.0;
+        data.phase = 0.75;
+        let mut lfo = Lfo::new(1000);
+        lfo.trigger(0, &data);
+        let (value, _) = lfo.get_sample(5, &data, false);
+        assert_eq!(value, 0.0);
+        let (value, _) = lfo.get_sample(15, &data, false);
+        assert!(value > 0.0 && value < 0.5 * 0.6);
+        let (value, _) = lfo.get_sample(20, &data, false);
+        assert!((value - 0.53).abs() < 0.001);
+    }
+
+    #[test]
+    fn one_shot_stops_after_one_cycle() {
+        let mut data = get_data();
+        data.retrigger = LfoRetrigger::OneShot;
+        let mut lfo = Lfo::new(1000);
+        lfo.trigger(0, &data);
+        let mut value = 0.0;
+        for i in 1..1500 {
+            let (v, _) = lfo.get_sample(i, &data, false);
+            value = v;
+        }
+        assert_eq!(value, 1.0);
+    }
+
+    #[test]
+    fn steps_are_played_in_order() {
+        let mut data = get_data();
+        data.select_wave(7);
+        data.num_steps = 4;
+        data.steps[..4].copy_from_slice(&[0.5, -0.5, 1.0, 0.0]);
+        let mut lfo = Lfo::new(1000);
+        lfo.reset(0, 0.0);
+        let mut values = vec!();
+        for i in 0..4 {
+            let (v, _) = lfo.get_sample(i * 250 + 100, &data, false);
+            values.push(v);
+        }
+        assert_eq!(values, vec!(0.5, -0.5, 1.0, 0.0));
+    }
+
+    #[test]
+    fn smoothing_glides_to_next_step() {
+        let mut data = get_data();
+        data.select_wave(7);
+        data.num_steps = 2;
+        data.steps[..2].copy_from_slice(&[0.0, 1.0]);
+        data.smoothing = 0.5;
+        let mut lfo = Lfo::new(1000);
+        lfo.reset(0, 0.0);
+        let (v, _) = lfo.get_sample(250, &data, false);
+        assert_eq!(v, 0.0);
+        let (v, _) = lfo.get_sample(375, &data, false);
+        assert!((v - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn edit_step_is_clamped_and_not_saved() {
+        let mut data = get_data();
+        data.set_edit_step(0);
+        assert_eq!(data.edit_step, 0);
+        data.set_edit_step(MAX_LFO_STEPS as i64 + 5);
+        assert_eq!(data.edit_step, MAX_LFO_STEPS - 1);
+        let json = serde_json::to_string(&data).unwrap();
+        assert!(!json.contains("edit_step"));
+        let loaded: LfoData = serde_json::from_str(&json.replace("\"smoothing\"", "\"edit_step\":99,\"smoothing\"")).unwrap();
+        assert_eq!(loaded.edit_step, 0);
+    }
+
+    #[test]
+    fn sync_keeps_stored_frequency() {
+        let mut data = get_data();
+        data.frequency = 3.0;
+        data.sync = SyncValue::Quarter;
+        data.update_bpm(120.0);
+        assert!((data.get_frequency() - 2.0).abs() < 0.0001);
+        assert_eq!(data.frequency, 3.0);
+        data.sync = SyncValue::Off;
+        assert_eq!(data.get_frequency(), 3.0);
+    }
+
+    #[test]
+    fn nudge_moves_phase_the_short_way() {
+        let mut lfo = Lfo::new(1000);
+        lfo.reset(0, 0.9);
+        lfo.nudge_phase(0.1, 0.5);
+        assert!(lfo.position.abs() < 0.0001 || (lfo.position - 1.0).abs() < 0.0001);
+        lfo.reset(0, 0.5);
+        lfo.nudge_phase(0.6, 0.1);
+        assert!((lfo.position - 0.51).abs() < 0.0001);
+    }
+
+    #[test]
+    fn free_running_lfo_keeps_phase_on_trigger() {
+        let mut data = get_data();
+        data.retrigger = LfoRetrigger::Free;
+        let mut lfo = Lfo::new(1000);
+        let (before, _) = lfo.get_sample(250, &data, false);
+        lfo.trigger(250, &data);
+        let (after, _) = lfo.get_sample(250, &data, false);
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn free_running_lfo_advances_during_delay() {
+        let mut data = get_data();
+        data.retrigger = LfoRetrigger::Free;
+        data.delay = 100.0;
+        let mut lfo = Lfo::new(1000);
+        lfo.get_sample(0, &data, false);
+        lfo.trigger(0, &data);
+        let (value, _) = lfo.get_sample(50, &data, false);
+        assert_eq!(value, 0.0);
+        assert!((lfo.position - 0.05).abs() < 0.0001);
+    }
 }
@@ -1,9 +1,11 @@
 pub mod delay;
 pub mod engine;
+pub mod env_follower;
 pub mod envelope;
 pub mod filter;
 pub mod lfo;
 pub mod oscillator;
+pub mod oversampler;
 pub mod sample_generator;
 pub mod synth;
 pub mod voice;
@@ -11,21 +13,25 @@ pub mod wt_oscillator;
 
 pub use delay::{Delay, DelayData};
 pub use engine::Engine;
-pub use envelope::{Envelope, EnvelopeData};
+pub use env_follower::EnvFollower;
+pub use envelope::{Envelope, EnvelopeData, MAX_ENV_POINTS};
 pub use filter::{Filter, FilterData, OnePole};
-pub use lfo::{Lfo, LfoData};
+pub use lfo::{Lfo, LfoData, LfoRetrigger, MAX_LFO_STEPS};
 pub use oscillator::{Oscillator, OscData, OscType, OscRouting};
+pub use oversampler::{Upsampler, Downsampler, MAX_OVERSAMPLING};
 pub use sample_generator::SampleGenerator;
 pub use synth::{
     Synth, PatchData, SynthState,
-    PlayMode, FilterRouting, VoiceAllocation, PanOrigin,
-    NUM_VOICES, NUM_GLOBAL_LFOS, NUM_MODULATORS
+    PlayMode, FilterRouting, VoiceAllocation, VoiceStealing, SameKey, NotePriority, PanOrigin, Oversampling,
+    GlideMode, GlideCurve,
+    NUM_VOICES, NUM_GLOBAL_LFOS, NUM_MODULATORS, NUM_MACROS
 };
 pub use wt_oscillator::{WtOsc, WtOscData};
 
 use super::Float;
-use super::MidiMessage;
-use super::{Parameter, SynthParam, ParamId, MenuItem};
+use super::{MidiMessage, MpeConfig};
+use super::{Parameter, SynthParam};
 use super::{SoundData, SyncValue};
 use super::SynthMessage;
+use super::{ModAccumulator, ModSums, ModTable};
 use super::UiMessage;
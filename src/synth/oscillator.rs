@@ -163,6 +163,10 @@ impl Oscillator {
         self.last_update = sample_clock;
     }
 
+    pub fn set_phase(&mut self, phase: Float) {
+        self.wt_osc.set_phase(phase);
+    }
+
     fn get_sample_noise() -> Float {
         (rand::random::<Float>() * 2.0) - 1.0
     }
@@ -0,0 +1,168 @@
+//! Up- and downsampling for running nonlinear stages at a higher sample rate.
+//!
+//! Both directions use cascaded 2x stages with a halfband FIR lowpass, so 4x
+//! oversampling is done with two stages. In a halfband filter every second
+//! coefficient is zero, which is skipped when convolving.
+
+use super::Float;
+
+/// Highest supported oversampling factor
+pub const MAX_OVERSAMPLING: usize = 4;
+
+// Number of nonzero coefficients on each side of the center tap
+const HALF_LEN: usize = 8;
+const NUM_TAPS: usize = 4 * HALF_LEN - 1;
+const CENTER: usize = NUM_TAPS / 2;
+
+/** Halfband lowpass filter with a cutoff at a quarter of the sample rate. */
+struct HalfbandFir {
+    coeff: [Float; NUM_TAPS],
+    history: [Float; NUM_TAPS * 2], // Doubled to avoid wrapping when convolving
+    pos: usize,
+}
+
+impl HalfbandFir {
+    fn new() -> Self {
+        // Windowed sinc with a Blackman window
+        let mut coeff = [0.0; NUM_TAPS];
+        let mut sum = 0.0;
+        for (n, c) in coeff.iter_mut().enumerate() {
+            let k = n as Float - CENTER as Float;
+            let sinc = if n == CENTER {
+                0.5
+            } else {
+                (std::f64::consts::PI * k / 2.0).sin() / (std::f64::consts::PI * k)
+            };
+            let phase = 2.0 * std::f64::consts::PI * n as Float / (NUM_TAPS - 1) as Float;
+            let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            *c = sinc * window;
+            sum += *c;
+        }
+        // Normalize to unity gain at DC
+        for c in coeff.iter_mut() {
+            *c /= sum;
+        }
+        HalfbandFir{coeff, history: [0.0; NUM_TAPS * 2], pos: 0}
+    }
+
+    fn reset(&mut self) {
+        self.history = [0.0; NUM_TAPS * 2];
+        self.pos = 0;
+    }
+
+    // Add a sample to the history without calculating an output.
+    fn push(&mut self, sample: Float) {
+        self.pos = if self.pos == 0 { NUM_TAPS - 1 } else { self.pos - 1 };
+        self.history[self.pos] = sample;
+        self.history[self.pos + NUM_TAPS] = sample;
+    }
+
+    fn process(&mut self, sample: Float) -> Float {
+        self.push(sample);
+        let h = &self.history[self.pos..self.pos + NUM_TAPS];
+        let mut result = self.coeff[CENTER] * h[CENTER];
+        for n in (0..NUM_TAPS).step_by(2) {
+            result += self.coeff[n] * h[n];
+        }
+        result
+    }
+}
+
+/** Converts a signal to a multiple of the sample rate. */
+pub struct Upsampler {
+    stage: [HalfbandFir; 2],
+}
+
+impl Upsampler {
+    pub fn new() -> Self {
+        Upsampler{stage: [HalfbandFir::new(), HalfbandFir::new()]}
+    }
+
+    pub fn reset(&mut self) {
+        for s in self.stage.iter_mut() {
+            s.reset();
+        }
+    }
+
+    /** Upsample a single sample by factor 2 or 4.
+     *
+     * The first factor entries of output are filled with the new samples.
+     */
+    pub fn process(&mut self, sample: Float, factor: usize, output: &mut [Float; MAX_OVERSAMPLING]) {
+        // Zero-stuffing loses half the energy, so the gain is doubled
+        let a = 2.0 * self.stage[0].process(sample);
+        let b = 2.0 * self.stage[0].process(0.0);
+        if factor == 2 {
+            output[0] = a;
+            output[1] = b;
+        } else {
+            output[0] = 2.0 * self.stage[1].process(a);
+            output[1] = 2.0 * self.stage[1].process(0.0);
+            output[2] = 2.0 * self.stage[1].process(b);
+            output[3] = 2.0 * self.stage[1].process(0.0);
+        }
+    }
+}
+
+/** Converts an oversampled signal back to the base sample rate. */
+pub struct Downsampler {
+    stage: [HalfbandFir; 2],
+}
+
+impl Downsampler {
+    pub fn new() -> Self {
+        Downsampler{stage: [HalfbandFir::new(), HalfbandFir::new()]}
+    }
+
+    pub fn reset(&mut self) {
+        for s in self.stage.iter_mut() {
+            s.reset();
+        }
+    }
+
+    /** Decimate the first factor entries of input to a single sample. */
+    pub fn process(&mut self, input: &[Float; MAX_OVERSAMPLING], factor: usize) -> Float {
+        let (a, b) = if factor == 2 {
+            (input[0], input[1])
+        } else {
+            self.stage[1].push(input[0]);
+            let a = self.stage[1].process(input[1]);
+            self.stage[1].push(input[2]);
+            let b = self.stage[1].process(input[3]);
+            (a, b)
+        };
+        self.stage[0].push(a);
+        self.stage[0].process(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dc_passes_with_unity_gain() {
+        for factor in [2, 4].iter() {
+            let mut up = Upsampler::new();
+            let mut down = Downsampler::new();
+            let mut buffer = [0.0; MAX_OVERSAMPLING];
+            let mut result = 0.0;
+            for _ in 0..100 {
+                up.process(1.0, *factor, &mut buffer);
+                result = down.process(&buffer, *factor);
+            }
+            assert!((result - 1.0).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn content_above_nyquist_is_removed() {
+        // Alternating samples at 2x are at the original Nyquist frequency
+        let mut down = Downsampler::new();
+        let mut result = 0.0;
+        for _ in 0..100 {
+            result = down.process(&[1.0, -1.0, 1.0, -1.0], 2);
+        }
+        assert!(result.abs() < 0.001);
+    }
+}
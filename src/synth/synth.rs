@@ -1,12 +1,15 @@
 use super::Delay;
 use super::{SynthMessage, UiMessage};
 use super::Envelope;
-use super::Lfo;
-use super::MidiMessage;
-use super::{Parameter, ParamId, SynthParam, MenuItem};
-use super::SoundData;
-use super::voice::Voice;
+use super::EnvFollower;
+use super::{Lfo, LfoData};
+use super::{MidiMessage, MpeConfig};
+use super::{Parameter, SynthParam};
+use super::{SoundData, SyncValue};
+use super::{ModAccumulator, ModTable};
+use super::voice::{Voice, UnisonState};
 use super::Oscillator;
+use super::{Upsampler, Downsampler, MAX_OVERSAMPLING};
 use super::Float;
 
 use std::sync::{Arc, Mutex};
@@ -20,9 +23,11 @@ use wavetable::{WtManager, WavetableRef, WtInfo};
 
 pub const NUM_VOICES: usize = 32;
 const NUM_KEYS: usize = 128;
-pub const NUM_MODULATORS: usize = 16;
+pub const NUM_MODULATORS: usize = 64; // Active modulators are tracked in a u64 bitmap
 pub const NUM_GLOBAL_LFOS: usize = 2;
+pub const NUM_MACROS: usize = 8;
 const REF_FREQUENCY: Float = 440.0;
+const CLOCK_PHASE_CORRECTION: Float = 0.05; // Part of the phase error of synced LFOs corrected per clock tick
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
 pub enum PlayMode {
@@ -111,6 +116,99 @@ impl VoiceAllocation {
     }
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum VoiceStealing {
+    Oldest,      // Steal the voice triggered first
+    Quietest,    // Steal the voice with the lowest amplitude envelope level
+    Released,    // Prefer voices with released keys, then the oldest
+    KeepLowest,  // Steal the oldest, but never the lowest held note
+    KeepHighest  // Steal the oldest, but never the highest held note
+}
+
+impl Default for VoiceStealing {
+    fn default() -> Self { VoiceStealing::Oldest }
+}
+
+impl VoiceStealing {
+    pub fn from_int(param: usize) -> VoiceStealing {
+        match param {
+            0 => VoiceStealing::Oldest,
+            1 => VoiceStealing::Quietest,
+            2 => VoiceStealing::Released,
+            3 => VoiceStealing::KeepLowest,
+            4 => VoiceStealing::KeepHighest,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            VoiceStealing::Oldest => 0,
+            VoiceStealing::Quietest => 1,
+            VoiceStealing::Released => 2,
+            VoiceStealing::KeepLowest => 3,
+            VoiceStealing::KeepHighest => 4,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum NotePriority {
+    Last, // The most recently pressed key plays
+    Low,  // The lowest held key plays
+    High  // The highest held key plays
+}
+
+impl Default for NotePriority {
+    fn default() -> Self { NotePriority::Last }
+}
+
+impl NotePriority {
+    pub fn from_int(param: usize) -> NotePriority {
+        match param {
+            0 => NotePriority::Last,
+            1 => NotePriority::Low,
+            2 => NotePriority::High,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            NotePriority::Last => 0,
+            NotePriority::Low => 1,
+            NotePriority::High => 2,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum SameKey {
+    Stack, // A repeated key gets a new voice, the old one keeps sounding
+    Reuse  // A repeated key retriggers the voice already playing it
+}
+
+impl Default for SameKey {
+    fn default() -> Self { SameKey::Stack }
+}
+
+impl SameKey {
+    pub fn from_int(param: usize) -> SameKey {
+        match param {
+            0 => SameKey::Stack,
+            1 => SameKey::Reuse,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            SameKey::Stack => 0,
+            SameKey::Reuse => 1,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum PanOrigin {
     Center,
@@ -141,6 +239,99 @@ impl PanOrigin {
     }
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum Oversampling {
+    Off, // Run filters and drive at the device sample rate
+    X2,  // 2x oversampling
+    X4   // 4x oversampling
+}
+
+impl Default for Oversampling {
+    fn default() -> Self { Oversampling::Off }
+}
+
+impl Oversampling {
+    pub fn from_int(param: usize) -> Oversampling {
+        match param {
+            0 => Oversampling::Off,
+            1 => Oversampling::X2,
+            2 => Oversampling::X4,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            Oversampling::Off => 0,
+            Oversampling::X2 => 1,
+            Oversampling::X4 => 2,
+        }
+    }
+
+    /// Factor by which the sample rate is multiplied
+    pub fn get_factor(&self) -> usize {
+        match self {
+            Oversampling::Off => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum GlideMode {
+    Always, // Glide from the last played note
+    Legato  // Only glide if another note is still held
+}
+
+impl Default for GlideMode {
+    fn default() -> Self { GlideMode::Always }
+}
+
+impl GlideMode {
+    pub fn from_int(param: usize) -> GlideMode {
+        match param {
+            0 => GlideMode::Always,
+            1 => GlideMode::Legato,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            GlideMode::Always => 0,
+            GlideMode::Legato => 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum GlideCurve {
+    ConstTime, // Glide time is independent of the interval
+    ConstRate  // Glide time is given per octave
+}
+
+impl Default for GlideCurve {
+    fn default() -> Self { GlideCurve::ConstTime }
+}
+
+impl GlideCurve {
+    pub fn from_int(param: usize) -> GlideCurve {
+        match param {
+            0 => GlideCurve::ConstTime,
+            1 => GlideCurve::ConstRate,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_int(&self) -> usize {
+        match self {
+            GlideCurve::ConstTime => 0,
+            GlideCurve::ConstRate => 1,
+        }
+    }
+}
+
 // Data of the currently selected sound patch
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
 pub struct PatchData {
@@ -156,6 +347,28 @@ pub struct PatchData {
     pub voice_spread: Float, // Degree of voice spreading in stereo field
     pub voice_allocation: VoiceAllocation,
     pub pan_origin: PanOrigin,
+    #[serde(default)]
+    pub oversampling: Oversampling, // Oversampling of voice filters and drive
+    #[serde(default)]
+    pub glide_time: Float,   // Portamento time in ms, 0 = off
+    #[serde(default)]
+    pub glide_mode: GlideMode,
+    #[serde(default)]
+    pub glide_curve: GlideCurve,
+    #[serde(default)]
+    pub voice_stealing: VoiceStealing,
+    #[serde(default)]
+    pub same_key: SameKey,
+    #[serde(default)]
+    pub note_priority: NotePriority,
+    #[serde(default = "PatchData::default_unison_voices")]
+    pub unison_voices: usize, // Number of voices triggered per key
+    #[serde(default)]
+    pub unison_detune: Float, // Detune between the outer unison voices in cents
+    #[serde(default)]
+    pub unison_spread: Float, // Stereo spread of the unison voices
+    #[serde(default)]
+    pub unison_phase: Float,  // Amount of random start phase of the unison voices
 }
 
 impl PatchData {
@@ -170,6 +383,21 @@ impl PatchData {
         self.voice_spread = 0.0;
         self.voice_allocation = VoiceAllocation::RoundRobin;
         self.pan_origin = PanOrigin::Center;
+        self.oversampling = Oversampling::Off;
+        self.glide_time = 0.0;
+        self.glide_mode = GlideMode::Always;
+        self.glide_curve = GlideCurve::ConstTime;
+        self.voice_stealing = VoiceStealing::Oldest;
+        self.same_key = SameKey::Stack;
+        self.note_priority = NotePriority::Last;
+        self.unison_voices = 1;
+        self.unison_detune = 0.0;
+        self.unison_spread = 0.0;
+        self.unison_phase = 0.0;
+    }
+
+    fn default_unison_voices() -> usize {
+        1
     }
 }
 
@@ -179,6 +407,24 @@ impl PatchData {
  */
 pub struct SynthState {
     pub freq_factor: Float,
+    pub mod_acc: ModAccumulator, // Output of the global modulators
+    pub modul: ModTable,         // Modulators of the current sound
+}
+
+// Last expression values received on an MPE member channel
+#[derive(Clone, Copy, Default)]
+struct NoteExpression {
+    bend: Float,     // -1.0 to 1.0
+    pressure: Float, // 0.0 to 1.0
+    timbre: Float,   // 0.0 to 1.0
+}
+
+// A key held in monophonic modes, waiting to take over when the playing key is released
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct HeldKey {
+    key: u8,
+    velocity: u8,
+    channel: u8,
 }
 
 pub struct Synth {
@@ -187,6 +433,7 @@ pub struct Synth {
     sound: SoundData,        // Sound patch as loaded from disk
     sound_global: SoundData, // Sound with global modulators applied
     sound_local: SoundData,  // Sound with voice-local modulators applied
+    local_changed: u64,      // Modulation slots in which sound_local differs from sound_global
     keymap: [Float; NUM_KEYS],
     wt_manager: WtManager,
 
@@ -194,20 +441,29 @@ pub struct Synth {
     voice: [Voice; NUM_VOICES],
     delay: Delay,
     glfo: [Lfo; NUM_GLOBAL_LFOS],
+    drive_up: [Upsampler; 2],     // Oversampling of the drive stage (L, R)
+    drive_down: [Downsampler; 2],
+    follower: EnvFollower, // Follows the level of the synth output
 
     // Current state
     num_voices_triggered: u32,
     voices_playing: u32, // Bitmap with currently playing voices
     trigger_seq: u64,
     last_clock: i64,
+    last_freq: Float, // Frequency of the last played note, start of a glide
+    song_pos: i64, // Position in MIDI clock ticks (24 per quarter note)
+    transport_running: bool, // MIDI Start or Continue received
     pitch_bend: Float,
     mod_wheel: Float,
     aftertouch: Float,
     sustain_pedal: Float, // Use a float, so that we can use it as mod source
+    controller: [Float; 128], // Last values of all MIDI controllers (for use as mod source)
     sender: Sender<UiMessage>,
     global_state: SynthState,
-    key_stack: Vec<u16>, // List of currently pressed keys (for Mono/ Legato modes)
+    key_stack: Vec<HeldKey>, // List of currently pressed keys (for Mono/ Legato modes)
     last_voice: usize, // Last voice selected with RoundRobin voice allocation
+    mpe: MpeConfig,
+    expression: [NoteExpression; 16], // Per-note expression of the MPE member channels
 
     // Extra oscillators to display the waveshape
     samplebuff_osc: Oscillator,
@@ -224,6 +480,10 @@ impl Synth {
         sound.init();
         sound_global.init();
         sound_local.init();
+        let mut mod_acc = ModAccumulator::new();
+        mod_acc.update(&sound.modul);
+        let mut modul = ModTable::new();
+        modul.load(&sound.modul);
         let mut wt_manager = WtManager::new(sample_rate as Float, "data");
         wt_manager.add_basic_tables(0);
         wt_manager.add_pwm_tables(1, 64);
@@ -252,23 +512,33 @@ impl Synth {
             sound,
             sound_global,
             sound_local,
+            local_changed: 0,
             keymap,
             wt_manager,
             voice,
             delay: Delay::new(sample_rate),
             glfo,
+            drive_up: [Upsampler::new(), Upsampler::new()],
+            drive_down: [Downsampler::new(), Downsampler::new()],
+            follower: EnvFollower::new(sample_rate as Float),
             num_voices_triggered: 0,
             voices_playing: 0,
             trigger_seq: 0,
             last_clock: 0i64,
+            last_freq: 0.0,
+            song_pos: 0,
+            transport_running: false,
             pitch_bend: 0.0,
             mod_wheel: 0.0,
             aftertouch: 0.0,
             sustain_pedal: 0.0,
+            controller: [0.0; 128],
             sender,
-            global_state: SynthState{freq_factor: 1.0},
-            key_stack: vec!(0; 128),
+            global_state: SynthState{freq_factor: 1.0, mod_acc, modul},
+            key_stack: Vec::with_capacity(128),
             last_voice: NUM_VOICES,
+            mpe: MpeConfig{..Default::default()},
+            expression: [NoteExpression{..Default::default()}; 16],
             samplebuff_osc: Oscillator::new(sample_rate, default_table),
             samplebuff_env: Envelope::new(sample_rate as Float),
             samplebuff_lfo: Lfo::new(sample_rate),
@@ -290,6 +560,7 @@ impl Synth {
                     SynthMessage::Wavetable(i) => locked_synth.handle_wavetable_info(i),
                     SynthMessage::SampleBuffer(m, p) => locked_synth.handle_sample_buffer(m, p),
                     SynthMessage::Bpm(b) => locked_synth.handle_bpm(b),
+                    SynthMessage::Mpe(c) => locked_synth.handle_mpe_config(c),
                     SynthMessage::Exit     => {
                         keep_running = false;
                         locked_synth.exit();
@@ -307,7 +578,9 @@ impl Synth {
     fn reset(&mut self) {
         self.voice.iter_mut().for_each(|v| v.reset());
         self.delay.reset();
+        self.follower.reset();
         self.key_stack.clear();
+        self.num_voices_triggered = 0;
     }
 
     // Get global modulation values.
@@ -316,46 +589,58 @@ impl Synth {
     // the global sound data.
     //
     fn get_mod_values(&mut self, sample_clock: i64) {
-        // Reset the global sound copy to the main sound, discarding values that
-        // were modulated for the previous sample. Complete copy is faster than
-        // looping over the modulators.
-        self.sound_global = self.sound;
-
-        // Then apply global modulators
-        let mut param_id = ParamId{..Default::default()};
-        let mut synth_param = SynthParam{..Default::default()};
-        for m in self.sound.modul.iter() {
-            if !m.active || !m.is_global {
+        let last_changed = self.global_state.mod_acc.get_changed();
+        self.global_state.mod_acc.reset();
+        for i in self.global_state.modul.get_active_ids() {
+            let m = self.global_state.modul.modul[i];
+
+            // Get output of global modulation sources
+            let source_value = if m.source_is_global { self.get_source_value(m.source_func, m.source_func_id, sample_clock) } else { 0.0 };
+            let via_value = if m.via_is_global { self.get_source_value(m.via_func, m.via_func_id, sample_clock) } else { 0.0 };
+            if !m.is_global {
+                // Local modulator, voices need the global values
+                self.global_state.modul.modul[i].source_value = source_value;
+                self.global_state.modul.modul[i].via_value = via_value;
                 continue;
             }
+            self.global_state.mod_acc.add(i, m.get_mod_value(source_value, via_value));
+        }
 
-            // Get modulator source output
-            let mod_val: Float = match m.source_func {
-                Parameter::GlobalLfo => {
-                    let (val, _) = self.glfo[m.source_func_id - 1].get_sample(sample_clock, &self.sound_global.glfo[m.source_func_id - 1], false);
-                    val
-                },
-                Parameter::Aftertouch => self.aftertouch,
-                Parameter::Pitchbend => self.pitch_bend,
-                Parameter::ModWheel => self.mod_wheel,
-                Parameter::SustainPedal => self.sustain_pedal,
-                _ => 0.0,
-            } * m.scale;
-
+        // Restore the targets that are no longer modulated, then apply the
+        // new values. Only the targets that changed are copied.
+        let changed = self.global_state.mod_acc.get_changed();
+        self.global_state.mod_acc.copy_targets(last_changed & !changed, &self.sound, &mut self.sound_global);
+        self.global_state.mod_acc.apply(&self.sound, &mut self.sound_global);
+        self.global_state.mod_acc.apply_modulators(&self.sound, &mut self.global_state.modul);
 
-            // Get current value of target parameter
-            param_id.set(m.target_func, m.target_func_id, m.target_param);
-            let mut current_val = self.sound_global.get_value(&param_id);
-            let mut val = current_val.as_float();
+        // The voice sound data has to follow the global changes
+        self.local_changed |= last_changed | changed;
+    }
 
-            // Update value
-            let dest_range = MenuItem::get_val_range(param_id.function, param_id.parameter);
-            val = dest_range.safe_add(val, mod_val);
+    // Copy the unmodulated sound to the modulated copies, after the sound
+    // was changed outside of the modulation.
+    fn sync_sound_copies(&mut self) {
+        self.sound_global = self.sound;
+        self.sound_local = self.sound;
+        self.global_state.mod_acc.reset();
+        self.local_changed = 0;
+    }
 
-            // Update parameter in global sound data
-            current_val.set_from_float(val);
-            synth_param.set(m.target_func, m.target_func_id, m.target_param, current_val);
-            self.sound_global.set_parameter(&synth_param);
+    // Get the current output of a global modulation source.
+    fn get_source_value(&mut self, func: Parameter, func_id: usize, sample_clock: i64) -> Float {
+        match func {
+            Parameter::GlobalLfo => {
+                let (val, _) = self.glfo[func_id - 1].get_sample(sample_clock, &self.sound_global.glfo[func_id - 1], false);
+                val
+            },
+            Parameter::Aftertouch => self.aftertouch,
+            Parameter::Pitchbend => self.pitch_bend,
+            Parameter::ModWheel => self.mod_wheel,
+            Parameter::SustainPedal => self.sustain_pedal,
+            Parameter::Controller => self.controller[func_id & 0x7F],
+            Parameter::Macro => self.sound.macros[func_id - 1],
+            Parameter::OutputFollower => self.follower.get_level(),
+            _ => 0.0,
         }
     }
 
@@ -370,7 +655,10 @@ impl Synth {
         if self.voices_playing > 0 {
             for i in 0..32 {
                 if self.voices_playing & (1 << i) > 0 {
-                    let (sample_l, sample_r) = self.voice[i].get_sample(sample_clock, &self.sound_global, &mut self.sound_local, &self.global_state);
+                    // Undo the modulation of the voice processed last
+                    self.global_state.mod_acc.copy_targets(self.local_changed, &self.sound_global, &mut self.sound_local);
+                    let (sample_l, sample_r) = self.voice[i].get_sample(sample_clock, &self.sound, &mut self.sound_local, &self.global_state);
+                    self.local_changed = self.voice[i].get_mod_changed();
                     value_l += sample_l;
                     value_r += sample_r;
                 }
@@ -379,8 +667,8 @@ impl Synth {
 
         // Apply clipping
         if self.sound_global.patch.drive > 0.0 {
-            value_l = (value_l * self.sound_global.patch.drive).tanh();
-            value_r = (value_r * self.sound_global.patch.drive).tanh();
+            value_l = self.apply_drive(value_l, 0);
+            value_r = self.apply_drive(value_r, 1);
         }
 
         // Pass sample into global effects
@@ -388,11 +676,27 @@ impl Synth {
 
         value_l *= self.sound_global.patch.level;
         value_r *= self.sound_global.patch.level;
+        self.follower.process((value_l + value_r) * 0.5);
 
         self.last_clock = sample_clock;
         (value_l, value_r)
     }
 
+    // Saturate a sample of one channel, oversampled if enabled in the patch.
+    fn apply_drive(&mut self, sample: Float, channel: usize) -> Float {
+        let drive = self.sound_global.patch.drive;
+        let factor = self.sound_global.patch.oversampling.get_factor();
+        if factor == 1 {
+            return (sample * drive).tanh();
+        }
+        let mut buffer = [0.0; MAX_OVERSAMPLING];
+        self.drive_up[channel].process(sample, factor, &mut buffer);
+        for s in buffer.iter_mut().take(factor) {
+            *s = (*s * drive).tanh();
+        }
+        self.drive_down[channel].process(&buffer, factor)
+    }
+
     /// Update the bitmap with currently active voices.
     pub fn update(&mut self) {
         self.voices_playing = 0;
@@ -437,6 +741,31 @@ impl Synth {
                     _ => ()
                 }
             }
+            Parameter::Envelope => {
+                match msg.parameter {
+                    Parameter::Attack | Parameter::Decay | Parameter::Release
+                    | Parameter::AttackSync | Parameter::DecaySync
+                    | Parameter::ReleaseSync => self.sound.env[msg.function_id - 1].update_bpm(self.sound.patch.bpm),
+                    _ => ()
+                }
+            }
+            Parameter::Lfo => {
+                match msg.parameter {
+                    Parameter::Frequency | Parameter::Sync
+                    | Parameter::Waveform | Parameter::NumSteps => self.sound.lfo[msg.function_id - 1].update_bpm(self.sound.patch.bpm),
+                    _ => ()
+                }
+            }
+            Parameter::GlobalLfo => {
+                match msg.parameter {
+                    Parameter::Frequency | Parameter::Sync
+                    | Parameter::Waveform | Parameter::NumSteps => {
+                        self.sound.glfo[msg.function_id - 1].update_bpm(self.sound.patch.bpm);
+                        self.sync_global_lfos();
+                    }
+                    _ => ()
+                }
+            }
             Parameter::Delay => {
                 match msg.parameter {
                     Parameter::Tone => self.delay.update(&self.sound.delay),
@@ -444,9 +773,20 @@ impl Synth {
                     _ => ()
                 }
             }
+            Parameter::Modulation => {
+                let id = msg.function_id - 1;
+                if msg.parameter == Parameter::Target {
+                    // Modulators might share a different target now, and a
+                    // modulator that was a target might keep modulated values
+                    self.global_state.modul.load(&self.sound.modul);
+                    self.global_state.mod_acc.update(&self.sound.modul);
+                } else {
+                    self.global_state.modul.set(id, &self.sound.modul[id]);
+                }
+            }
             Parameter::Patch => {
                 match msg.parameter {
-                    Parameter::Bpm => self.delay.update_bpm(&mut self.sound.delay, self.sound.patch.bpm),
+                    Parameter::Bpm => self.update_bpm(),
                     Parameter::Voices | Parameter::Spread
                     | Parameter::Allocation | Parameter::PanOrigin => self.update_voice_allocation(),
                     _ => ()
@@ -454,6 +794,7 @@ impl Synth {
             }
             _ => ()
         }
+        self.sync_sound_copies();
     }
 
     fn update_delay_speed(&mut self) {
@@ -487,6 +828,10 @@ impl Synth {
             PanOrigin::Left   => self.set_voice_alloc_left(num_voices, spread),
             PanOrigin::Right  => self.set_voice_alloc_right(num_voices, spread),
         }
+        for (i, v) in self.voice.iter_mut().enumerate().take(num_voices) {
+            let index = if num_voices > 1 { i as Float / (num_voices - 1) as Float } else { 0.0 };
+            v.set_index(index);
+        }
     }
 
     fn set_voice_alloc_center(&mut self, num_voices: usize, spread: Float) {
@@ -520,18 +865,43 @@ impl Synth {
 
     fn handle_midi_message(&mut self, msg: MidiMessage) {
         match msg {
-            MidiMessage::NoteOn{channel: _, key, velocity} => self.handle_note_on(key, velocity),
-            MidiMessage::NoteOff{channel: _, key, velocity} => self.handle_note_off(key, velocity),
-            MidiMessage::KeyAT{channel: _, key: _, pressure: _} => (), // Polyphonic aftertouch not supported yet
-            MidiMessage::ChannelAT{channel: _, pressure} => self.handle_channel_aftertouch(pressure),
-            MidiMessage::Pitchbend{channel: _, pitch} => self.handle_pitch_bend(pitch),
-            MidiMessage::ControlChg{channel: _, controller, value} => self.handle_controller(controller, value),
+            MidiMessage::NoteOn{channel, key, velocity} => self.handle_note_on(channel, key, velocity),
+            MidiMessage::NoteOff{channel, key, velocity} => self.handle_note_off(channel, key, velocity),
+            MidiMessage::KeyAT{channel, key, pressure} => self.handle_key_aftertouch(channel, key, pressure),
+            MidiMessage::ChannelAT{channel, pressure} => {
+                if self.mpe.is_member_channel(channel) {
+                    self.expression[channel as usize].pressure = pressure as Float / 127.0;
+                    self.update_expression(channel);
+                } else {
+                    self.handle_channel_aftertouch(pressure);
+                }
+            }
+            MidiMessage::Pitchbend{channel, pitch} => {
+                if self.mpe.is_member_channel(channel) {
+                    self.expression[channel as usize].bend = (pitch + (pitch & 0x01)) as Float / 8192.0;
+                    self.update_expression(channel);
+                } else {
+                    self.handle_pitch_bend(pitch);
+                }
+            }
+            MidiMessage::ControlChg{channel, controller, value} => {
+                if self.mpe.is_member_channel(channel) {
+                    // Controller 74 = MPE timbre, other controllers are
+                    // ignored on member channels.
+                    if controller == 74 {
+                        self.expression[channel as usize].timbre = value as Float / 127.0;
+                        self.update_expression(channel);
+                    }
+                } else {
+                    self.handle_controller(controller, value);
+                }
+            }
             MidiMessage::ProgramChg{channel: _, program: _} => (), // This shouldn't get here, it's a UI event
-            MidiMessage::SongPos{position: _} => (),
-            MidiMessage::TimingClock => (),
-            MidiMessage::Start => (),
-            MidiMessage::Continue => (),
-            MidiMessage::Stop => (),
+            MidiMessage::SongPos{position} => self.handle_song_position(position),
+            MidiMessage::TimingClock => self.handle_timing_clock(),
+            MidiMessage::Start => self.handle_start(),
+            MidiMessage::Continue => self.handle_continue(),
+            MidiMessage::Stop => self.transport_running = false,
             MidiMessage::ActiveSensing => (),
             MidiMessage::Reset => (),
         }
@@ -541,8 +911,8 @@ impl Synth {
     fn handle_sound_update(&mut self, sound: &SoundData) {
         self.reset();
         self.sound = *sound;
-        self.sound_global = self.sound;
-        self.sound_local = self.sound;
+        self.global_state.modul.load(&self.sound.modul);
+        self.global_state.mod_acc.update(&self.sound.modul);
         self.update_wavetable(0);
         self.update_wavetable(1);
         self.update_wavetable(2);
@@ -550,6 +920,7 @@ impl Synth {
         self.update_routing(1);
         self.update_routing(2);
         self.update_voice_allocation();
+        self.update_bpm();
     }
 
     fn handle_wavetable_info(&mut self, mut wt_info: WtInfo) {
@@ -559,49 +930,254 @@ impl Synth {
     /// Received updated BPM by TimingClock MIDI message
     fn handle_bpm(&mut self, bpm: Float) {
         self.sound.patch.bpm = bpm;
+        self.update_bpm();
+    }
+
+    // Update all tempo-synced components to the current patch tempo.
+    fn update_bpm(&mut self) {
+        let bpm = self.sound.patch.bpm;
         self.delay.update_bpm(&mut self.sound.delay, bpm);
+        for env in self.sound.env.iter_mut() {
+            env.update_bpm(bpm);
+        }
+        for lfo in self.sound.lfo.iter_mut() {
+            lfo.update_bpm(bpm);
+        }
+        for glfo in self.sound.glfo.iter_mut() {
+            glfo.update_bpm(bpm);
+        }
+        self.sync_sound_copies();
+    }
+
+    fn handle_start(&mut self) {
+        self.song_pos = 0;
+        self.transport_running = true;
+        self.sync_global_lfos();
+    }
+
+    fn handle_continue(&mut self) {
+        self.transport_running = true;
+        self.sync_global_lfos();
+    }
+
+    // Song position is counted in sixteenth notes, which are 6 clock ticks.
+    fn handle_song_position(&mut self, position: u16) {
+        self.song_pos = position as i64 * 6;
+        self.sync_global_lfos();
+    }
+
+    // The LFOs run at the tempo tracked from the clock, only small phase
+    // errors are corrected gradually to avoid audible steps.
+    fn handle_timing_clock(&mut self) {
+        if self.transport_running {
+            self.song_pos += 1;
+            for (i, lfo) in self.glfo.iter_mut().enumerate() {
+                if let Some(position) = Synth::get_synced_position(&self.sound.glfo[i], self.song_pos) {
+                    lfo.nudge_phase(position, CLOCK_PHASE_CORRECTION);
+                }
+            }
+        }
+    }
+
+    // Align the phase of tempo-synced global LFOs to the song position, so
+    // that they stay locked to the sequencer.
+    fn sync_global_lfos(&mut self) {
+        for (i, lfo) in self.glfo.iter_mut().enumerate() {
+            if let Some(position) = Synth::get_synced_position(&self.sound.glfo[i], self.song_pos) {
+                lfo.reset(self.last_clock, position);
+            }
+        }
     }
 
-    fn handle_note_on(&mut self, key: u8, velocity: u8) {
+    // Phase of a synced LFO at the given song position, None if sync is off.
+    fn get_synced_position(data: &LfoData, song_pos: i64) -> Option<Float> {
+        data.get_cycle_sixteenths().map(|sixteenths| {
+            let ticks_per_cycle = sixteenths * 6.0;
+            (song_pos as Float / ticks_per_cycle + data.phase).fract()
+        })
+    }
+
+    fn handle_mpe_config(&mut self, config: MpeConfig) {
+        self.mpe = config;
+        self.expression = [NoteExpression{..Default::default()}; 16];
+    }
+
+    // Send the expression of an MPE member channel to the voice playing on it.
+    fn update_expression(&mut self, channel: u8) {
+        for v in &mut self.voice {
+            if v.is_triggered() && v.channel == channel {
+                Synth::apply_expression(v, &self.expression[channel as usize], self.mpe.bend_range);
+            }
+        }
+    }
+
+    fn apply_expression(voice: &mut Voice, expr: &NoteExpression, bend_range: Float) {
+        let bend_factor = (expr.bend * bend_range / 12.0).exp2();
+        voice.set_expression(bend_factor, expr.pressure, expr.timbre);
+    }
+
+    fn handle_note_on(&mut self, channel: u8, key: u8, velocity: u8) {
         info!("Note: {}", key);
+        if self.sound.patch.play_mode != PlayMode::Poly && !self.has_priority(key) {
+            // A held key with higher priority keeps playing, the new key
+            // only takes over once that one is released.
+            self.key_stack.push(HeldKey{key, velocity, channel});
+            return;
+        }
+        self.start_note(channel, key, velocity);
+    }
+
+    // Check if a new key takes over from the keys held in monophonic modes.
+    fn has_priority(&self, key: u8) -> bool {
+        match self.sound.patch.note_priority {
+            NotePriority::Last => true,
+            NotePriority::Low => self.key_stack.iter().all(|k| k.key > key),
+            NotePriority::High => self.key_stack.iter().all(|k| k.key < key),
+        }
+    }
+
+    // Remove the held key that plays next from the key stack.
+    fn pop_priority_key(&mut self) -> Option<HeldKey> {
+        let pos = match self.sound.patch.note_priority {
+            NotePriority::Last => self.key_stack.len().checked_sub(1),
+            NotePriority::Low => self.key_stack.iter().enumerate().min_by_key(|(_, k)| k.key).map(|(i, _)| i),
+            NotePriority::High => self.key_stack.iter().enumerate().max_by_key(|(_, k)| k.key).map(|(i, _)| i),
+        };
+        pos.map(|p| self.key_stack.remove(p))
+    }
+
+    // Trigger the voices for a new note. With unison, a single key triggers
+    // several voices, which all share the same trigger sequence number.
+    fn start_note(&mut self, channel: u8, key: u8, velocity: u8) {
         let freq = self.keymap[key as usize];
-        let voice_id = self.select_voice();
-        let voice = &mut self.voice[voice_id];
-        voice.set_key(key);
-        voice.set_freq(freq);
-        voice.set_velocity(velocity, self.sound.patch.vel_sens);
-        voice.trigger(self.trigger_seq, self.last_clock, &self.sound);
-        self.key_stack.push((velocity as u16) << 8 | (key as u16));
-        self.num_voices_triggered += 1;
+        let glide = self.get_glide_start().map(|from_freq| (from_freq, self.get_glide_samples(from_freq, freq)));
+        self.last_freq = freq;
+        let num_unison = self.get_num_unison();
+        for i in 0..num_unison {
+            let unison = self.get_unison_state(i, num_unison);
+            let voice_id = self.select_voice(channel, key, i);
+            let voice = &mut self.voice[voice_id];
+            voice.set_key(key);
+            voice.set_channel(channel);
+            if self.mpe.is_member_channel(channel) {
+                // Expression sent before the note on is the initial value
+                Synth::apply_expression(voice, &self.expression[channel as usize], self.mpe.bend_range);
+            }
+            voice.set_freq(freq);
+            if let Some((from_freq, num_samples)) = glide {
+                voice.start_glide(from_freq, num_samples);
+            }
+            voice.set_unison(unison);
+            voice.set_velocity(velocity, self.sound.patch.vel_sens);
+            if !voice.is_triggered() {
+                // A reused or mono voice that is still held is only counted once
+                self.num_voices_triggered += 1;
+            }
+            voice.trigger(self.trigger_seq, self.last_clock, &self.sound);
+            self.voices_playing |= 1 << voice_id;
+        }
+        self.key_stack.push(HeldKey{key, velocity, channel});
         self.trigger_seq += 1;
-        self.voices_playing |= 1 << voice_id;
     }
 
-    fn handle_note_off(&mut self, key: u8, velocity: u8) {
+    // Number of voices played per key, limited by the available voices.
+    fn get_num_unison(&self) -> usize {
+        let patch = &self.sound.patch;
+        patch.unison_voices.max(1).min(patch.num_voices)
+    }
+
+    // Get detune, panning and level of a voice in a unison stack.
+    //
+    // The voices are distributed evenly between the negative and positive
+    // detune and spread values.
+    fn get_unison_state(&self, index: usize, num_unison: usize) -> UnisonState {
+        if num_unison < 2 {
+            return UnisonState::default();
+        }
+        let patch = &self.sound.patch;
+        let position = index as Float / (num_unison - 1) as Float * 2.0 - 1.0; // -1.0 to 1.0
+        UnisonState{
+            detune: (position * patch.unison_detune / 2400.0).exp2(),
+            pan: position * patch.unison_spread / 2.0,
+            gain: 1.0 / (num_unison as Float).sqrt(),
+            phase: patch.unison_phase,
+        }
+    }
+
+    // Get the frequency a new note glides from, None if there is no glide.
+    //
+    // Monophonic modes glide from the current pitch of the voice, poly mode
+    // from the last played note.
+    fn get_glide_start(&self) -> Option<Float> {
+        let patch = &self.sound.patch;
+        if patch.glide_time <= 0.0 || self.last_freq <= 0.0 {
+            return None;
+        }
+        let mono = patch.play_mode != PlayMode::Poly;
+        let note_held = !self.key_stack.is_empty() || (mono && self.voice[0].is_triggered());
+        if patch.glide_mode == GlideMode::Legato && !note_held {
+            return None;
+        }
+        if mono && self.voice[0].is_running() {
+            Some(self.voice[0].get_current_freq())
+        } else {
+            Some(self.last_freq)
+        }
+    }
+
+    // Get the duration of a glide in samples.
+    fn get_glide_samples(&self, from_freq: Float, to_freq: Float) -> Float {
+        let patch = &self.sound.patch;
+        let samples = patch.glide_time * self.sample_rate as Float / 1000.0;
+        match patch.glide_curve {
+            GlideCurve::ConstTime => samples,
+            GlideCurve::ConstRate => samples * (to_freq / from_freq).log2().abs(), // Glide time is per octave
+        }
+    }
+
+    fn handle_note_off(&mut self, channel: u8, key: u8, velocity: u8) {
         // If key is on the key stack, remove it. It might not be on the stack
         // if the sound was switched while holding the key.
-        let position = self.key_stack.iter().position(|x| *x as u8 == key);
+        let match_channel = self.mpe.is_member_channel(channel);
+        let position = self.key_stack.iter().position(|k| k.key == key && (!match_channel || k.channel == channel));
         if let Some(pos) = position {
             self.key_stack.remove(pos);
         }
-        // Find the voice playing this key and trigger the release phase.
-        for v in &mut self.voice {
-            if v.is_triggered() && v.key == key {
-                if self.sound.patch.play_mode == PlayMode::Poly || self.key_stack.is_empty() {
-                    // In poly mode, or if no other notes are held, we release
-                    // the voice.
-                    self.num_voices_triggered -= 1;
-                    v.key_release(velocity, self.sustain_pedal > 0.0, &self.sound);
-                } else {
-                    // For Mono and Legato play modes, we continue playing an
-                    // older note still on the stack (still triggered).
-                    if let Some(new_key) = self.key_stack.pop() {
-                        self.handle_note_on(new_key as u8, (new_key >> 8) as u8);
-                    } else {
-                        panic!("Retrieving note from stack failed.");
+        // Find the voice playing this key and trigger the release phase. With
+        // MPE, the same key can be played on several channels.
+        let found = self.voice.iter()
+                              .find(|v| v.is_triggered() && v.key == key && (!match_channel || v.channel == channel))
+                              .map(|v| v.trigger_seq);
+        if let Some(trigger_seq) = found {
+            let pedal_held = self.sustain_pedal > 0.0;
+            if self.sound.patch.play_mode == PlayMode::Poly || self.key_stack.is_empty() || pedal_held {
+                // In poly mode, or if no other notes are held, we release
+                // the voice. In mono modes, the sustain pedal keeps the
+                // released note playing instead of switching to a held one.
+                // All voices of a unison stack are released together.
+                for v in &mut self.voice {
+                    if v.is_triggered() && v.key == key && v.trigger_seq == trigger_seq {
+                        self.num_voices_triggered -= 1;
+                        v.key_release(velocity, pedal_held, &self.sound);
                     }
                 }
-                break;
+            } else {
+                // For Mono and Legato play modes, we continue playing the
+                // held note with the highest priority (still triggered).
+                if let Some(held) = self.pop_priority_key() {
+                    self.start_note(held.channel, held.key, held.velocity);
+                }
+            }
+        }
+    }
+
+    // Polyphonic aftertouch only affects the voice playing the key. With MPE,
+    // the channel has to match as well.
+    fn handle_key_aftertouch(&mut self, channel: u8, key: u8, pressure: u8) {
+        let match_channel = self.mpe.is_member_channel(channel);
+        for v in &mut self.voice {
+            if v.is_triggered() && v.key == key && (!match_channel || v.channel == channel) {
+                v.set_key_aftertouch(pressure);
             }
         }
     }
@@ -618,6 +1194,7 @@ impl Synth {
 
     // Map controllers with a special function to dedicated parameters
     fn handle_controller(&mut self, ctrl: u8, value: u8) {
+        self.controller[ctrl as usize & 0x7F] = value as Float / 127.0;
         match ctrl {
             0x01 => {
                 // Controller 1 = Modulation wheel
@@ -640,7 +1217,18 @@ impl Synth {
     }
 
     // If any voices have still-running envelopes, trigger the release.
+    //
+    // In mono modes, a note sustained by the pedal hands over to the keys
+    // that are still held, if there are any.
     fn handle_pedal_release(&mut self) {
+        if self.sound.patch.play_mode != PlayMode::Poly
+        && self.voice[0].is_running()
+        && !self.voice[0].is_triggered() {
+            if let Some(held) = self.pop_priority_key() {
+                self.start_note(held.channel, held.key, held.velocity);
+                return;
+            }
+        }
         for v in &mut self.voice {
             if v.is_running() {
                 v.pedal_release(&self.sound);
@@ -648,15 +1236,39 @@ impl Synth {
         }
     }
 
-    // Decide which voice gets to play the next note.
-    fn select_voice(&mut self) -> usize {
+    // Decide which voice gets to play the next note. The index is the
+    // position of the voice in a unison stack.
+    fn select_voice(&mut self, channel: u8, key: u8, index: usize) -> usize {
         match self.sound.patch.play_mode {
-            PlayMode::Poly => self.select_voice_poly(),
-            PlayMode::Mono => 0,   // Monophonic modes always use voice 0
-            PlayMode::Legato => 0,
+            PlayMode::Poly => {
+                if self.sound.patch.same_key == SameKey::Reuse {
+                    if let Some(id) = self.find_voice_playing(channel, key) {
+                        return id;
+                    }
+                }
+                let id = self.select_voice_poly();
+                if self.voice[id].is_running() {
+                    // No free voice was found, the running note is cut off
+                    self.voice[id].steal();
+                }
+                id
+            }
+            PlayMode::Mono => index,   // Monophonic modes always use the first voices
+            PlayMode::Legato => index,
         }
     }
 
+    // Find a running voice playing the given key, e.g. in release phase or
+    // held by the sustain pedal. With MPE, the channel has to match as well.
+    // Voices already started for the current note are skipped.
+    fn find_voice_playing(&self, channel: u8, key: u8) -> Option<usize> {
+        let match_channel = self.mpe.is_member_channel(channel);
+        self.voice[..self.sound.patch.num_voices]
+            .iter()
+            .position(|v| v.is_running() && v.key == key && (!match_channel || v.channel == channel)
+                          && v.trigger_seq != self.trigger_seq)
+    }
+
     fn select_voice_poly(&mut self) -> usize {
         match self.sound.patch.voice_allocation {
             VoiceAllocation::RoundRobin => self.select_voice_round_robin(),
@@ -667,10 +1279,8 @@ impl Synth {
 
     // The voices are selected sequentially, wrapping back to the beginning
     // when the end of the range is reached. If no free voice is available,
-    // the oldest voice is stolen.
+    // a running voice is stolen.
     fn select_voice_round_robin(&mut self) -> usize {
-        let mut min_trigger_seq = std::u64::MAX;
-        let mut min_id = 0;
         let mut i: usize = self.last_voice + 1;
         if i >= self.sound.patch.num_voices {
             i = 0;
@@ -680,42 +1290,75 @@ impl Synth {
                 self.last_voice = i;
                 return i;
             }
-            // min_id will contain the ID of the voice with the lowest
-            // trigger sequence number, which is the "oldest" voice in the
-            // current context.
-            if self.voice[i].trigger_seq < min_trigger_seq {
-                min_trigger_seq = self.voice[i].trigger_seq;
-                min_id = i;
-            }
             i += 1;
             if i >= self.sound.patch.num_voices {
                 i = 0;
             }
         }
-        // No free voice found, so steal the oldest running voice
-        self.last_voice = min_id;
-        min_id
+        // No free voice found, so steal a running voice
+        self.last_voice = self.select_voice_to_steal();
+        self.last_voice
     }
 
     // First free voice with the lowest ID is selected. If no free voice is
-    // available, the oldest voice is stolen.
+    // available, a running voice is stolen.
     fn select_voice_start_to_end(&mut self) -> usize {
-        let mut min_trigger_seq = std::u64::MAX;
-        let mut min_id = 0;
         for i in 0..self.sound.patch.num_voices {
             if !self.voice[i].is_running() { // TODO: Optimize array access
                 return i;
             }
-            // min_id will contain the ID of the voice with the lowest
-            // trigger sequence number, which is the "oldest" voice in the
-            // current context.
-            if self.voice[i].trigger_seq < min_trigger_seq {
-                min_trigger_seq = self.voice[i].trigger_seq;
-                min_id = i;
+        }
+        // No free voice found, so steal a running voice
+        self.select_voice_to_steal()
+    }
+
+    // Select the running voice to be replaced by a new note, according to the
+    // voice stealing strategy of the patch.
+    //
+    // Voices are ranked by a strategy-specific value first (lower is stolen
+    // first), the trigger sequence number decides between equal values, so
+    // the oldest voice is stolen if nothing else applies. Voices of the
+    // unison stack being started are never stolen. If all other voices are
+    // protected, the oldest of them is stolen anyway.
+    fn select_voice_to_steal(&self) -> usize {
+        let strategy = self.sound.patch.voice_stealing;
+        let voices = &self.voice[..self.sound.patch.num_voices];
+        let held_keys = voices.iter()
+                              .filter(|v| v.is_triggered() && v.trigger_seq != self.trigger_seq)
+                              .map(|v| v.key);
+        let protected_key = match strategy {
+            VoiceStealing::KeepLowest => held_keys.min(),
+            VoiceStealing::KeepHighest => held_keys.max(),
+            _ => None,
+        };
+        let mut min_value = Float::MAX;
+        let mut min_trigger_seq = u64::MAX;
+        let mut min_id = None;
+        let mut oldest_trigger_seq = u64::MAX;
+        let mut oldest_id = 0;
+        for (i, v) in voices.iter().enumerate() {
+            if v.trigger_seq == self.trigger_seq {
+                continue; // Don't steal voices of the unison stack being started
+            }
+            if v.trigger_seq < oldest_trigger_seq {
+                oldest_trigger_seq = v.trigger_seq;
+                oldest_id = i;
+            }
+            if v.is_triggered() && Some(v.key) == protected_key {
+                continue;
+            }
+            let value = match strategy {
+                VoiceStealing::Quietest => v.get_level(),
+                VoiceStealing::Released if v.is_triggered() => 1.0,
+                _ => 0.0,
+            };
+            if value < min_value || (value == min_value && v.trigger_seq < min_trigger_seq) {
+                min_value = value;
+                min_trigger_seq = v.trigger_seq;
+                min_id = Some(i);
             }
         }
-        // No free voice found, so steal the oldest running voice
-        min_id
+        min_id.unwrap_or(oldest_id)
     }
 
     // Select random index, then do search for free voice from there.
@@ -753,11 +1396,9 @@ impl Synth {
             },
             Parameter::Envelope => {
                 let env_data = &mut self.sound.env[param.function_id - 1];
-                let mut len_total = env_data.delay + env_data.attack + env_data.decay + env_data.release;
-                if !env_data.looping {
-                    len_total += len_total / 3.0; // Add 25% duration for sustain, value is in ms
-                }
-                let mut release_point = len_total - env_data.release;
+                let (held, release) = env_data.get_duration(); // Includes sustain, values are in ms
+                let mut len_total = held + release;
+                let mut release_point = held;
                 len_total *= 44.1; // Samples per second
                 release_point *= 44.1;
                 let samples_per_slot = (len_total / len as Float) as usize; // Number of samples per slot in the buffer
@@ -795,6 +1436,7 @@ impl Synth {
                 };
                 lfo.reset(0, sound_copy.phase);
                 sound_copy.frequency = freq;
+                sound_copy.sync = SyncValue::Off;
                 // Get first sample explicitly to reset LFO (for S&H)
                 let (sample, _) = lfo.get_sample(0, &sound_copy, true);
                 buffer[0] = sample;
@@ -808,3 +1450,411 @@ impl Synth {
         self.sender.send(UiMessage::SampleBuffer(buffer, param)).unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FunctionId, ParamId, ParameterValue};
+
+    // The synth is too large for the default stack of the test threads, so
+    // run the test in a thread with a bigger stack.
+    fn with_synth(play_mode: PlayMode, test: impl FnOnce(&mut Synth) + Send + 'static) {
+        std::thread::Builder::new().stack_size(32 * 1024 * 1024).spawn(move || {
+            let (sender, _) = crossbeam_channel::unbounded();
+            let mut synth = Synth::new(1000, sender);
+            synth.sound.patch.play_mode = play_mode;
+            test(&mut synth);
+        }).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn mono_handover_keeps_channel_of_held_key() {
+        with_synth(PlayMode::Mono, |synth| {
+            synth.handle_note_on(1, 60, 100);
+            synth.handle_note_on(2, 64, 100);
+            assert_eq!(synth.voice[0].channel, 2);
+            synth.handle_note_off(2, 64, 0);
+            assert_eq!(synth.voice[0].key, 60);
+            assert_eq!(synth.voice[0].channel, 1);
+        });
+    }
+
+    // Play keys with four voices allocated from first to last, so that the
+    // voices are used in the order of the keys.
+    fn play_four_voices(synth: &mut Synth, stealing: VoiceStealing, keys: &[u8]) {
+        synth.sound.patch.num_voices = 4;
+        synth.sound.patch.voice_allocation = VoiceAllocation::FirstToLast;
+        synth.sound.patch.voice_stealing = stealing;
+        for key in keys {
+            synth.handle_note_on(0, *key, 100);
+        }
+    }
+
+    // Get the ID of the voice playing a key
+    fn find_voice(synth: &Synth, key: u8) -> Option<usize> {
+        synth.voice.iter().position(|v| v.is_triggered() && v.key == key)
+    }
+
+    #[test]
+    fn oldest_voice_is_stolen() {
+        with_synth(PlayMode::Poly, |synth| {
+            play_four_voices(synth, VoiceStealing::Oldest, &[60, 62, 64, 65]);
+            synth.handle_note_on(0, 67, 100);
+            assert_eq!(find_voice(synth, 67), Some(0));
+        });
+    }
+
+    #[test]
+    fn released_voice_is_stolen_first() {
+        with_synth(PlayMode::Poly, |synth| {
+            play_four_voices(synth, VoiceStealing::Released, &[60, 62, 64, 65]);
+            synth.handle_note_off(0, 64, 0);
+            assert!(synth.voice[2].is_running());
+            synth.handle_note_on(0, 67, 100);
+            assert_eq!(find_voice(synth, 67), Some(2));
+        });
+    }
+
+    #[test]
+    fn quietest_voice_is_stolen() {
+        with_synth(PlayMode::Poly, |synth| {
+            synth.sound.patch.vel_sens = 1.0;
+            play_four_voices(synth, VoiceStealing::Quietest, &[]);
+            for (key, velocity) in [(60, 100), (62, 100), (64, 20), (65, 100)].iter() {
+                synth.handle_note_on(0, *key, *velocity);
+            }
+            synth.sync_sound_copies();
+            for t in 0..200 {
+                synth.get_sample(t);
+            }
+            synth.handle_note_on(0, 67, 100);
+            assert_eq!(find_voice(synth, 67), Some(2));
+        });
+    }
+
+    #[test]
+    fn lowest_and_highest_keys_are_kept() {
+        with_synth(PlayMode::Poly, |synth| {
+            play_four_voices(synth, VoiceStealing::KeepLowest, &[60, 65, 64, 62]);
+            synth.handle_note_on(0, 67, 100);
+            assert_eq!(find_voice(synth, 60), Some(0));
+            assert_eq!(find_voice(synth, 67), Some(1));
+        });
+        with_synth(PlayMode::Poly, |synth| {
+            play_four_voices(synth, VoiceStealing::KeepHighest, &[65, 60, 64, 62]);
+            synth.handle_note_on(0, 59, 100);
+            assert_eq!(find_voice(synth, 65), Some(0));
+            assert_eq!(find_voice(synth, 59), Some(1));
+        });
+    }
+
+    #[test]
+    fn oldest_voice_is_stolen_if_all_are_protected() {
+        with_synth(PlayMode::Poly, |synth| {
+            play_four_voices(synth, VoiceStealing::KeepLowest, &[60, 60, 60, 60]);
+            synth.handle_note_on(0, 67, 100);
+            assert_eq!(find_voice(synth, 67), Some(0));
+            assert_eq!(synth.voice.iter().filter(|v| v.is_triggered() && v.key == 60).count(), 3);
+        });
+    }
+
+    // Get the keys on the key stack, in order
+    fn get_held_keys(synth: &Synth) -> Vec<u8> {
+        synth.key_stack.iter().map(|k| k.key).collect()
+    }
+
+    #[test]
+    fn last_note_priority_returns_to_previous_key() {
+        with_synth(PlayMode::Mono, |synth| {
+            for key in [60, 64, 62].iter() {
+                synth.handle_note_on(0, *key, 100);
+            }
+            assert_eq!(synth.voice[0].key, 62);
+            assert_eq!(get_held_keys(synth), vec!(60, 64, 62));
+            synth.handle_note_off(0, 62, 0);
+            assert_eq!(synth.voice[0].key, 64);
+            synth.handle_note_off(0, 64, 0);
+            assert_eq!(synth.voice[0].key, 60);
+            assert_eq!(get_held_keys(synth), vec!(60));
+        });
+    }
+
+    #[test]
+    fn low_note_priority_plays_lowest_key() {
+        with_synth(PlayMode::Mono, |synth| {
+            synth.sound.patch.note_priority = NotePriority::Low;
+            for key in [64, 60, 67].iter() {
+                synth.handle_note_on(0, *key, 100);
+            }
+            assert_eq!(synth.voice[0].key, 60);
+            synth.handle_note_off(0, 60, 0);
+            assert_eq!(synth.voice[0].key, 64);
+            assert!(synth.voice[0].is_triggered());
+        });
+    }
+
+    #[test]
+    fn high_note_priority_plays_highest_key() {
+        with_synth(PlayMode::Mono, |synth| {
+            synth.sound.patch.note_priority = NotePriority::High;
+            for key in [60, 67, 64].iter() {
+                synth.handle_note_on(0, *key, 100);
+            }
+            assert_eq!(synth.voice[0].key, 67);
+            synth.handle_note_off(0, 64, 0); // Released key that isn't playing
+            assert_eq!(synth.voice[0].key, 67);
+            assert_eq!(get_held_keys(synth), vec!(60, 67));
+            synth.handle_note_off(0, 67, 0);
+            assert_eq!(synth.voice[0].key, 60);
+        });
+    }
+
+    #[test]
+    fn sustained_note_hands_over_on_pedal_release() {
+        with_synth(PlayMode::Mono, |synth| {
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_on(0, 64, 100);
+            synth.handle_controller(0x40, 127);
+            synth.handle_note_off(0, 64, 0);
+            assert_eq!(synth.voice[0].key, 64); // Kept playing by the pedal
+            assert!(!synth.voice[0].is_triggered());
+            assert!(synth.voice[0].is_running());
+            synth.handle_controller(0x40, 0);
+            assert_eq!(synth.voice[0].key, 60);
+            assert!(synth.voice[0].is_triggered());
+        });
+    }
+
+    #[test]
+    fn glide_duration_depends_on_curve() {
+        with_synth(PlayMode::Mono, |synth| {
+            synth.sound.patch.glide_time = 100.0;
+            synth.sound.patch.glide_curve = GlideCurve::ConstTime;
+            assert_eq!(synth.get_glide_samples(220.0, 440.0), 100.0);
+            assert_eq!(synth.get_glide_samples(880.0, 220.0), 100.0);
+            synth.sound.patch.glide_curve = GlideCurve::ConstRate;
+            assert_eq!(synth.get_glide_samples(220.0, 440.0), 100.0);
+            assert_eq!(synth.get_glide_samples(880.0, 220.0), 200.0);
+        });
+    }
+
+    #[test]
+    fn legato_glide_needs_held_key() {
+        with_synth(PlayMode::Mono, |synth| {
+            synth.sound.patch.glide_time = 100.0;
+            synth.sound.patch.glide_mode = GlideMode::Legato;
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_off(0, 60, 0);
+            synth.handle_note_on(0, 64, 100);
+            assert_eq!(synth.voice[0].get_current_freq(), synth.keymap[64]);
+            synth.handle_note_on(0, 67, 100);
+            assert_eq!(synth.voice[0].get_current_freq(), synth.keymap[64]);
+
+            synth.sound.patch.glide_mode = GlideMode::Always;
+            synth.handle_note_off(0, 67, 0);
+            synth.handle_note_off(0, 64, 0);
+            synth.handle_note_on(0, 60, 100);
+            assert_eq!(synth.voice[0].get_current_freq(), synth.keymap[64]);
+        });
+    }
+
+    #[test]
+    fn glide_ends_on_target_pitch() {
+        with_synth(PlayMode::Mono, |synth| {
+            synth.sound.patch.glide_time = 100.0;
+            synth.sync_sound_copies();
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_on(0, 67, 100);
+            let target = synth.keymap[67];
+            let mut num_samples = 0;
+            while synth.voice[0].get_current_freq() != target && num_samples < 1000 {
+                synth.get_sample(num_samples);
+                num_samples += 1;
+            }
+            assert!(num_samples == 100 || num_samples == 101);
+            synth.get_sample(num_samples);
+            assert_eq!(synth.voice[0].get_current_freq(), target);
+        });
+    }
+
+    // Get the IDs of all running voices
+    fn get_running_voices(synth: &Synth) -> Vec<usize> {
+        (0..NUM_VOICES).filter(|i| synth.voice[*i].is_running()).collect()
+    }
+
+    #[test]
+    fn reuse_retriggers_releasing_voice() {
+        with_synth(PlayMode::Poly, |synth| {
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_off(0, 60, 0);
+            let first = get_running_voices(synth);
+            assert_eq!(first.len(), 1);
+
+            // Stack starts a second voice for the same key
+            synth.handle_note_on(0, 60, 100);
+            assert_eq!(get_running_voices(synth).len(), 2);
+            synth.handle_note_off(0, 60, 0);
+
+            synth.sound.patch.same_key = SameKey::Reuse;
+            synth.handle_note_on(0, 60, 100);
+            let running = get_running_voices(synth);
+            assert_eq!(running.len(), 2);
+            assert_eq!(running.iter().filter(|i| synth.voice[**i].is_triggered()).count(), 1);
+        });
+    }
+
+    #[test]
+    fn reuse_retriggers_pedal_held_voice() {
+        with_synth(PlayMode::Poly, |synth| {
+            synth.sound.patch.same_key = SameKey::Reuse;
+            synth.handle_controller(0x40, 127);
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_off(0, 60, 0);
+            let held = get_running_voices(synth);
+            synth.handle_note_on(0, 60, 100);
+            assert_eq!(get_running_voices(synth), held);
+            assert!(synth.voice[held[0]].is_triggered());
+        });
+    }
+
+    #[test]
+    fn reuse_retriggers_unison_stack() {
+        with_synth(PlayMode::Poly, |synth| {
+            synth.sound.patch.same_key = SameKey::Reuse;
+            synth.sound.patch.unison_voices = 3;
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_off(0, 60, 0);
+            let stack = get_running_voices(synth);
+            assert_eq!(stack.len(), 3);
+            synth.handle_note_on(0, 60, 100);
+            assert_eq!(get_running_voices(synth), stack);
+            assert!(stack.iter().all(|i| synth.voice[*i].is_triggered()));
+        });
+    }
+
+    #[test]
+    fn unison_voices_are_spread_evenly() {
+        with_synth(PlayMode::Poly, |synth| {
+            synth.sound.patch.unison_detune = 20.0;
+            synth.sound.patch.unison_spread = 1.0;
+            let low = synth.get_unison_state(0, 3);
+            let mid = synth.get_unison_state(1, 3);
+            let high = synth.get_unison_state(2, 3);
+            assert!((low.detune.log2() * 1200.0 + 10.0).abs() < 0.0001); // -10 cents
+            assert_eq!(mid.detune, 1.0);
+            assert!((high.detune.log2() * 1200.0 - 10.0).abs() < 0.0001);
+            assert_eq!((low.pan, mid.pan, high.pan), (-0.5, 0.0, 0.5));
+            assert!((low.gain - 1.0 / (3.0 as Float).sqrt()).abs() < 0.0001);
+
+            let single = synth.get_unison_state(0, 1);
+            assert_eq!((single.detune, single.pan, single.gain), (1.0, 0.0, 1.0));
+        });
+    }
+
+    #[test]
+    fn unison_is_limited_to_available_voices() {
+        with_synth(PlayMode::Poly, |synth| {
+            synth.sound.patch.unison_voices = 8;
+            synth.sound.patch.num_voices = 4;
+            synth.handle_note_on(0, 60, 100);
+            assert_eq!(get_running_voices(synth), vec!(0, 1, 2, 3));
+        });
+    }
+
+    #[test]
+    fn note_off_releases_whole_unison_stack() {
+        with_synth(PlayMode::Poly, |synth| {
+            synth.sound.patch.unison_voices = 3;
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_on(0, 64, 100);
+            synth.handle_note_off(0, 60, 0);
+            let triggered: Vec<u8> = synth.voice.iter().filter(|v| v.is_triggered()).map(|v| v.key).collect();
+            assert_eq!(triggered, vec!(64, 64, 64));
+            assert_eq!(synth.num_voices_triggered, 3);
+        });
+    }
+
+    #[test]
+    fn macro_value_reaches_targets() {
+        with_synth(PlayMode::Poly, |synth| {
+            let mut sound = synth.sound;
+            let targets = [ParamId::new(Parameter::Patch, 1, Parameter::Level),
+                           ParamId::new(Parameter::Filter, 1, Parameter::Cutoff)];
+            for (m, target) in sound.modul.iter_mut().zip(targets.iter()) {
+                m.set_source(&FunctionId{function: Parameter::Macro, function_id: 1});
+                m.set_target(target);
+                m.set_amount(0.5);
+                m.active = true;
+            }
+            synth.handle_sound_update(&sound);
+
+            synth.handle_ui_message(SynthParam::new(Parameter::Macro, 1, Parameter::Level, ParameterValue::Float(0.0)));
+            synth.get_sample(0);
+            let level = synth.sound_global.patch.level;
+            let cutoff = synth.sound_global.filter[0].cutoff;
+
+            synth.handle_ui_message(SynthParam::new(Parameter::Macro, 1, Parameter::Level, ParameterValue::Float(1.0)));
+            synth.get_sample(1);
+            assert!(synth.sound_global.patch.level > level);
+            assert!(synth.sound_global.filter[0].cutoff > cutoff);
+            assert_eq!(synth.sound.patch.level, sound.patch.level);
+            assert_eq!(synth.sound.filter[0].cutoff, sound.filter[0].cutoff);
+        });
+    }
+
+    #[test]
+    fn modulator_amount_can_be_modulated() {
+        with_synth(PlayMode::Poly, |synth| {
+            let mut sound = synth.sound;
+            sound.modul[0].set_source(&FunctionId{function: Parameter::Macro, function_id: 1});
+            sound.modul[0].set_target(&ParamId::new(Parameter::Modulation, 2, Parameter::Amount));
+            sound.modul[0].set_amount(1.0);
+            sound.modul[0].active = true;
+            sound.modul[1].set_source(&FunctionId{function: Parameter::Macro, function_id: 2});
+            sound.modul[1].set_target(&ParamId::new(Parameter::Patch, 1, Parameter::Level));
+            sound.modul[1].set_amount(0.0);
+            sound.modul[1].active = true;
+            sound.macros[1] = 1.0;
+            synth.handle_sound_update(&sound);
+            let level = synth.sound.patch.level;
+
+            synth.get_sample(0);
+            synth.get_sample(1);
+            assert_eq!(synth.sound_global.patch.level, level);
+
+            // Macro 1 covers the full amount range of -1.0 to 1.0
+            synth.handle_ui_message(SynthParam::new(Parameter::Macro, 1, Parameter::Level, ParameterValue::Float(0.25)));
+            synth.get_sample(2);
+            synth.get_sample(3);
+            assert_eq!(synth.global_state.modul.modul[1].amount, 0.5);
+            assert!(synth.sound_global.patch.level > level);
+            assert_eq!(synth.sound.modul[1].amount, 0.0);
+
+            // The patch value is restored when the modulation ends
+            synth.handle_ui_message(SynthParam::new(Parameter::Macro, 1, Parameter::Level, ParameterValue::Float(0.0)));
+            synth.get_sample(4);
+            synth.get_sample(5);
+            assert_eq!(synth.global_state.modul.modul[1].amount, 0.0);
+            assert_eq!(synth.sound_global.patch.level, level);
+        });
+    }
+
+    #[test]
+    fn retriggered_voices_are_counted_once() {
+        with_synth(PlayMode::Poly, |synth| {
+            synth.sound.patch.same_key = SameKey::Reuse;
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_on(0, 60, 100);
+            assert_eq!(synth.num_voices_triggered, 1);
+            synth.handle_note_off(0, 60, 0);
+            assert_eq!(synth.num_voices_triggered, 0);
+        });
+        with_synth(PlayMode::Mono, |synth| {
+            synth.handle_note_on(0, 60, 100);
+            synth.handle_note_on(0, 64, 100);
+            synth.handle_note_off(0, 64, 0);
+            synth.handle_note_off(0, 60, 0);
+            assert_eq!(synth.num_voices_triggered, 0);
+        });
+    }
+}
@@ -1,12 +1,15 @@
 use super::Envelope;
-use super::Filter;
+use super::EnvFollower;
+use super::{Filter, FilterData};
 use super::Float;
 use super::Lfo;
-use super::{Parameter, ParamId, SynthParam, MenuItem};
+use super::Parameter;
 use super::{PlayMode, FilterRouting};
 use super::SynthState;
 use super::{Oscillator, OscData};
+use super::{Upsampler, Downsampler, MAX_OVERSAMPLING};
 use super::SoundData;
+use super::ModSums;
 
 use wavetable::{Wavetable, WavetableRef};
 
@@ -17,25 +20,66 @@ pub const NUM_ENVELOPES: usize = 3;
 pub const NUM_FILTERS: usize = 2;
 pub const NUM_LFOS: usize = 2;
 
+// Duration of the fade out of a stolen note in seconds
+const STEAL_FADE_TIME: Float = 0.005;
+
+// Per-voice settings for playing as part of a unison stack
+#[derive(Copy, Clone, Debug)]
+pub struct UnisonState {
+    pub detune: Float, // Frequency factor of the voice
+    pub pan: Float,    // Offset to the panning of the voice
+    pub gain: Float,   // Level compensation for the number of stacked voices
+    pub phase: Float,  // Amount of random oscillator start phase, 0.0 - 1.0
+}
+
+impl Default for UnisonState {
+    fn default() -> Self {
+        UnisonState{detune: 1.0, pan: 0.0, gain: 1.0, phase: 0.0}
+    }
+}
+
 pub struct Voice {
     // Components
     osc: [Oscillator; NUM_OSCILLATORS],
     env: [Envelope; NUM_ENVELOPES],
     pub filter: [Filter; NUM_FILTERS],
     lfo: [Lfo; NUM_LFOS],
+    follower: EnvFollower, // Follows the level of the oscillator mix
+    upsampler: [Upsampler; NUM_FILTERS],
+    downsampler: Downsampler,
 
     // Static config
+    sample_rate: Float,   // Device sample rate
+    pan: Float,           // Panning of this voice in the stereo field
     pan_l: Float,         // Panning of this voice in the stereo field
     pan_r: Float,         // Panning of this voice in the stereo field
+    fade_step: Float,     // Decrease of the fade gain per sample
 
     // Current state
     triggered: bool,
     pub trigger_seq: u64, // Sequence number for keeping track of trigger order
     pub key: u8,          // Key that was pressed to trigger this voice
+    pub channel: u8,      // MIDI channel of the note (used to match MPE expression)
     velocity: Float,      // Raw velocity of NoteOn event (for use as modulation source)
     scaled_vel: Float,    // Velocity scaled according to sound settings (for use as amplifier)
+    key_aftertouch: Float, // Polyphonic aftertouch of the played key (for use as modulation source)
+    timbre: Float,        // MPE timbre (CC 74) of the note (for use as modulation source)
+    bend_factor: Float,   // MPE pitch bend of the note as frequency factor
+    rel_velocity: Float,  // Velocity of NoteOff event (for use as modulation source)
+    random: Float,        // Random value chosen at note trigger (for use as modulation source)
+    index: Float,         // Position of this voice in the active voices (for use as modulation source)
+    mod_sums: ModSums,    // Sums of the local modulators
     input_freq: Float,    // Frequency to play as received from Synth
+    glide_offset: Float,  // Remaining pitch offset of a running glide in octaves
+    glide_step: Float,    // Change of the glide offset per sample
+    unison: UnisonState,  // Settings of the voice as part of a unison stack
     last_update: i64,
+    oversampling: usize,  // Current oversampling factor of the filter section
+
+    // Anti-click fade when a running voice is stolen
+    last_out: [Float; 2], // Last output sample (L, R)
+    fade_out: [Float; 2], // Output of the previous note when it was cut off
+    fade_gain: Float,     // Remaining gain of the previous note output
 }
 
 impl Voice {
@@ -63,15 +107,36 @@ impl Voice {
                 env,
                 filter,
                 lfo,
+                follower: EnvFollower::new(sample_rate as Float),
+                upsampler: [Upsampler::new(), Upsampler::new()],
+                downsampler: Downsampler::new(),
+                sample_rate: sample_rate as Float,
+                pan: 0.5,
                 pan_l: 0.5,
                 pan_r: 0.5,
+                fade_step: 1.0 / (STEAL_FADE_TIME * sample_rate as Float),
                 triggered: false,
                 trigger_seq: 0,
                 key: 0,
+                channel: 0,
                 velocity: 0.0,
                 scaled_vel: 0.0,
+                key_aftertouch: 0.0,
+                timbre: 0.0,
+                bend_factor: 1.0,
+                rel_velocity: 0.0,
+                random: 0.0,
+                index: 0.0,
+                mod_sums: ModSums::new(),
                 input_freq: 440.0,
-                last_update: 0i64}
+                glide_offset: 0.0,
+                glide_step: 0.0,
+                unison: UnisonState::default(),
+                last_update: 0i64,
+                oversampling: 1,
+                last_out: [0.0; 2],
+                fade_out: [0.0; 2],
+                fade_gain: 0.0}
     }
 
     pub fn reset(&mut self) {
@@ -82,6 +147,9 @@ impl Voice {
         for f in &mut self.filter {
             f.reset();
         }
+        self.follower.reset();
+        self.last_out = [0.0; 2];
+        self.fade_gain = 0.0;
     }
 
     fn get_frequency(data: &OscData, input_freq: Float) -> Float {
@@ -94,67 +162,71 @@ impl Voice {
         freq
     }
 
-    fn get_mod_values(&mut self, sample_clock: i64, sound_global: &SoundData, sound_local: &mut SoundData) {
-        // Get modulated values from global sound and discard values that were
-        // modulated for the previous sample. Complete copy is faster than
-        // looping over the modulators.
-        *sound_local = *sound_global;
-
-        // Then update the local sound with mod values
-        let mut param_id = ParamId{..Default::default()};
-        let mut synth_param = SynthParam{..Default::default()};
-        for m in sound_global.modul.iter() {
-
-            if !m.active {
+    // Calculate the local modulators and apply them to the voice sound data.
+    //
+    // The local sound data is expected to match the global one, except for
+    // the targets reported by get_mod_changed() of the voice processed last.
+    fn get_mod_values(&mut self, sample_clock: i64, sound: &SoundData, sound_local: &mut SoundData, global_state: &SynthState) {
+        self.mod_sums.reset();
+        for i in global_state.modul.get_active_ids() {
+            let m = &global_state.modul.modul[i];
+            if m.is_global {
                 continue;
             }
 
-            // Get current value of target parameter
-            param_id.set(m.target_func, m.target_func_id, m.target_param);
-            let mut current_val = sound_local.get_value(&param_id);
-
-            if !m.is_global {
-
-                // Get modulator source output
-                let mod_val: Float = match m.source_func {
-                    Parameter::Oscillator => {
-                        let id = m.source_func_id - 1;
-                        let freq = Voice::get_frequency(&sound_local.osc[id], self.input_freq);
-                        let (val, _) = self.osc[id].get_sample(freq, sample_clock, &sound_local.osc[id], false);
-                        val
-                    },
-                    Parameter::Lfo => {
-                        let (val, _) = self.lfo[m.source_func_id - 1].get_sample(sample_clock, &sound_local.lfo[m.source_func_id - 1], false);
-                        val
-                    },
-                    Parameter::Envelope => {
-                        self.env[m.source_func_id - 1].get_sample(sample_clock, &sound_local.env[m.source_func_id - 1])
-                    }
-                    Parameter::Velocity => {
-                        self.velocity
-                    }
-                    _ => 0.0,
-                } * m.scale;
-
-                let mut val = current_val.as_float();
+            // Get modulator source output, global sources have already
+            // been evaluated by the synth
+            let source_value = if m.source_is_global {
+                m.source_value
+            } else {
+                self.get_source_value(m.source_func, m.source_func_id, sample_clock, sound_local)
+            };
+            let via_value = if m.via_is_global {
+                m.via_value
+            } else {
+                self.get_source_value(m.via_func, m.via_func_id, sample_clock, sound_local)
+            };
+            global_state.mod_acc.add_local(&mut self.mod_sums, i, m.get_mod_value(source_value, via_value));
+        }
 
-                // Update value
-                let dest_range = MenuItem::get_val_range(param_id.function, param_id.parameter);
-                val = dest_range.safe_add(val, mod_val);
+        // Update targets of local modulators in voice sound data
+        global_state.mod_acc.apply_local(&self.mod_sums, sound, sound_local);
+    }
 
-                // Update parameter in voice sound data
-                current_val.set_from_float(val);
-            }
+    /** Bitmap of the modulation slots changed in the local sound data. */
+    pub fn get_mod_changed(&self) -> u64 {
+        self.mod_sums.get_changed()
+    }
 
-            // TODO: Too much copying
-            synth_param.set(m.target_func, m.target_func_id, m.target_param, current_val);
-            sound_local.set_parameter(&synth_param);
+    // Get the current output of a voice-local modulation source.
+    fn get_source_value(&mut self, func: Parameter, func_id: usize, sample_clock: i64, sound_local: &SoundData) -> Float {
+        match func {
+            Parameter::Oscillator => {
+                let id = func_id - 1;
+                let freq = Voice::get_frequency(&sound_local.osc[id], self.input_freq);
+                let (val, _) = self.osc[id].get_sample(freq, sample_clock, &sound_local.osc[id], false);
+                val
+            },
+            Parameter::Lfo => {
+                let (val, _) = self.lfo[func_id - 1].get_sample(sample_clock, &sound_local.lfo[func_id - 1], false);
+                val
+            },
+            Parameter::Envelope => self.env[func_id - 1].get_sample(sample_clock, &sound_local.env[func_id - 1]),
+            Parameter::Follower => self.follower.get_level(),
+            Parameter::Velocity => self.velocity,
+            Parameter::KeyAftertouch => self.key_aftertouch,
+            Parameter::Timbre => self.timbre,
+            Parameter::KeyValue => self.key as Float,
+            Parameter::Random => self.random,
+            Parameter::RelVelocity => self.rel_velocity,
+            Parameter::VoiceIndex => self.index,
+            _ => 0.0,
         }
     }
 
     pub fn get_sample(&mut self,
                       sample_clock: i64,
-                      sound_global: &SoundData,
+                      sound: &SoundData,
                       sound_local: &mut SoundData,
                       global_state: &SynthState) -> (Float, Float) {
         if !self.is_running() {
@@ -163,33 +235,41 @@ impl Voice {
         let mut input_f1 = 0.0;
         let mut input_f2 = 0.0;
         let mut result_direct = 0.0;
+        let mut osc_out = [0.0; NUM_OSCILLATORS];
         self.last_update = sample_clock;
         let mut reset = false;
-        let input_freq = self.input_freq * global_state.freq_factor;
+        let mut osc_mix = 0.0;
+        let input_freq = self.input_freq * global_state.freq_factor * self.bend_factor * self.update_glide() * self.unison.detune;
         let mut freq: Float;
 
         // Prepare modulation values
-        self.get_mod_values(sample_clock, sound_global, sound_local);
+        self.get_mod_values(sample_clock, sound, sound_local, global_state);
 
         // Get mixed output from oscillators
         for (i, osc) in self.osc.iter_mut().enumerate() {
             freq = Voice::get_frequency(&sound_local.osc[i], input_freq);
             let (sample, wave_complete) = osc.get_sample(freq, sample_clock, &sound_local.osc[i], reset);
+            osc_out[i] = sample;
             // TODO: Add panning here
             let sample_amped = sample * sound_local.osc[i].level * self.scaled_vel;
             input_f1      += sample_amped * osc.filter1_out;
             input_f2      += sample_amped * osc.filter2_out;
             result_direct += sample_amped * osc.direct_out;
+            osc_mix       += sample_amped;
             // Sync oscillator 1 to 0
             reset = i == 0 && wave_complete && sound_local.osc[1].sync == 1;
         }
 
+        // Track the level of the mix for use as modulation source
+        self.follower.process(osc_mix);
+
         // Feed it into the filters
         let mut result: Float = self.apply_filter(sample_clock,
                                                   sound_local,
                                                   input_f1,
                                                   input_f2,
-                                                  input_freq);
+                                                  input_freq,
+                                                  &osc_out);
         result += result_direct;
 
         // Apply the volume envelope
@@ -203,10 +283,20 @@ impl Voice {
             result = -1.0;
         }
 
+        result *= self.unison.gain;
+
         // Pan result
         // TODO: Use actual panning algorithm
-        let result_l = result * self.pan_l;
-        let result_r = result * self.pan_r;
+        let mut result_l = result * self.pan_l;
+        let mut result_r = result * self.pan_r;
+
+        // Fade out the last output of a cut off note to avoid clicks
+        if self.fade_gain > 0.0 {
+            result_l += self.fade_out[0] * self.fade_gain;
+            result_r += self.fade_out[1] * self.fade_gain;
+            self.fade_gain = (self.fade_gain - self.fade_step).max(0.0);
+        }
+        self.last_out = [result_l, result_r];
 
         (result_l, result_r)
     }
@@ -215,10 +305,46 @@ impl Voice {
                         sample_clock: i64,
                         sound_local: &mut SoundData,
                         input_f1: Float,
-                        mut input_f2: Float,
-                        input_freq: Float) -> Float {
-        let filter_env = self.env[1].get_sample(sample_clock, &sound_local.env[1]); // Env2 is normaled to filter cutoff
-        let output_f1  = self.filter[0].process(input_f1, &mut sound_local.filter[0], input_freq, filter_env);
+                        input_f2: Float,
+                        input_freq: Float,
+                        osc_out: &[Float; NUM_OSCILLATORS]) -> Float {
+        let filter_env = [
+            self.get_filter_env(sample_clock, sound_local, 0),
+            self.get_filter_env(sample_clock, sound_local, 1),
+        ];
+        let fm = [
+            Voice::get_filter_fm(&sound_local.filter[0], osc_out),
+            Voice::get_filter_fm(&sound_local.filter[1], osc_out),
+        ];
+
+        let factor = sound_local.patch.oversampling.get_factor();
+        if factor != self.oversampling {
+            self.set_oversampling(factor);
+        }
+        if factor == 1 {
+            return self.process_filters(sound_local, input_f1, input_f2, input_freq, &filter_env, &fm);
+        }
+
+        // Run the filters at the oversampled rate
+        let mut buf_f1 = [0.0; MAX_OVERSAMPLING];
+        let mut buf_f2 = [0.0; MAX_OVERSAMPLING];
+        let mut buf_out = [0.0; MAX_OVERSAMPLING];
+        self.upsampler[0].process(input_f1, factor, &mut buf_f1);
+        self.upsampler[1].process(input_f2, factor, &mut buf_f2);
+        for i in 0..factor {
+            buf_out[i] = self.process_filters(sound_local, buf_f1[i], buf_f2[i], input_freq, &filter_env, &fm);
+        }
+        self.downsampler.process(&buf_out, factor)
+    }
+
+    fn process_filters(&mut self,
+                       sound_local: &mut SoundData,
+                       input_f1: Float,
+                       mut input_f2: Float,
+                       input_freq: Float,
+                       filter_env: &[Float; NUM_FILTERS],
+                       fm: &[Float; NUM_FILTERS]) -> Float {
+        let output_f1  = self.filter[0].process(input_f1, &mut sound_local.filter[0], input_freq, filter_env[0], fm[0]);
         let mut result = match sound_local.patch.filter_routing {
             FilterRouting::Parallel => {
                 output_f1
@@ -228,16 +354,102 @@ impl Voice {
                 0.0
             }
         };
-        result += self.filter[1].process(input_f2, &mut sound_local.filter[1], input_freq, filter_env);
+        result += self.filter[1].process(input_f2, &mut sound_local.filter[1], input_freq, filter_env[1], fm[1]);
         result
     }
 
+    // Switch the filter section to a new oversampling factor
+    fn set_oversampling(&mut self, factor: usize) {
+        self.oversampling = factor;
+        for f in &mut self.filter {
+            f.set_sample_rate(self.sample_rate * factor as Float);
+        }
+        for u in &mut self.upsampler {
+            u.reset();
+        }
+        self.downsampler.reset();
+    }
+
+    // Get the output of the envelope selected as cutoff modulation source
+    fn get_filter_env(&mut self, sample_clock: i64, sound_local: &SoundData, filter_id: usize) -> Float {
+        let env_id = sound_local.filter[filter_id].env_source.clamp(1, NUM_ENVELOPES) - 1;
+        self.env[env_id].get_sample(sample_clock, &sound_local.env[env_id])
+    }
+
+    // Get the output of the oscillator selected as filter FM source
+    fn get_filter_fm(data: &FilterData, osc_out: &[Float; NUM_OSCILLATORS]) -> Float {
+        if data.fm_source > 0 && data.fm_source as usize <= NUM_OSCILLATORS {
+            osc_out[data.fm_source as usize - 1]
+        } else {
+            0.0
+        }
+    }
+
     pub fn set_key(&mut self, key: u8) {
         self.key = key;
+        self.key_aftertouch = 0.0;
+        self.timbre = 0.0;
+        self.bend_factor = 1.0;
+    }
+
+    // Set the position in the active voices, from 0.0 to 1.0
+    pub fn set_index(&mut self, index: Float) {
+        self.index = index;
+    }
+
+    pub fn set_channel(&mut self, channel: u8) {
+        self.channel = channel;
+    }
+
+    /** Set the per-note expression of an MPE note.
+     *
+     * Pressure and timbre go from 0.0 to 1.0 and are available as modulation
+     * sources, bend is the frequency factor of the note pitch bend.
+     */
+    pub fn set_expression(&mut self, bend_factor: Float, pressure: Float, timbre: Float) {
+        self.bend_factor = bend_factor;
+        self.key_aftertouch = pressure;
+        self.timbre = timbre;
+    }
+
+    pub fn set_key_aftertouch(&mut self, pressure: u8) {
+        self.key_aftertouch = pressure as Float / 127.0;
     }
 
     pub fn set_freq(&mut self, freq: Float) {
         self.input_freq = freq;
+        self.glide_offset = 0.0;
+    }
+
+    /** Frequency currently played, including a running glide. */
+    pub fn get_current_freq(&self) -> Float {
+        self.input_freq * self.glide_offset.exp2()
+    }
+
+    /** Glide from the given frequency to the current one.
+     *
+     * The glide is linear in pitch and takes the given number of samples.
+     */
+    pub fn start_glide(&mut self, from_freq: Float, num_samples: Float) {
+        if num_samples < 1.0 {
+            self.glide_offset = 0.0;
+            return;
+        }
+        self.glide_offset = (from_freq / self.input_freq).log2();
+        self.glide_step = self.glide_offset.abs() / num_samples;
+    }
+
+    // Advance a running glide, returns the current frequency factor.
+    fn update_glide(&mut self) -> Float {
+        if self.glide_offset == 0.0 {
+            return 1.0;
+        }
+        if self.glide_offset.abs() <= self.glide_step {
+            self.glide_offset = 0.0;
+        } else {
+            self.glide_offset -= self.glide_step * self.glide_offset.signum();
+        }
+        self.glide_offset.exp2()
     }
 
     pub fn set_velocity(&mut self, velocity: u8, sensitivity: Float) {
@@ -253,6 +465,22 @@ impl Voice {
 
     // Set panning. 0.0 = left, 1.0 = right
     pub fn set_pan(&mut self, pan: Float) {
+        self.pan = pan;
+        self.update_pan();
+    }
+
+    /** Set the state of the voice as member of a unison stack.
+     *
+     * The pan offset is added to the panning of the voice, the detune factor
+     * is applied to the played frequency.
+     */
+    pub fn set_unison(&mut self, unison: UnisonState) {
+        self.unison = unison;
+        self.update_pan();
+    }
+
+    fn update_pan(&mut self) {
+        let pan = (self.pan + self.unison.pan).clamp(0.0, 1.0);
         self.pan_l = 1.0 - pan;
         self.pan_r = pan;
     }
@@ -261,6 +489,20 @@ impl Voice {
         self.osc[osc_id].update_routing(osc_data);
     }
 
+    /** Prepare the voice for being taken over by a new note.
+     *
+     * The note is cut off, so its last output sample is ramped down to zero
+     * over STEAL_FADE_TIME to avoid a click. The level of the old note is not
+     * used as modulation value for the new one.
+     */
+    pub fn steal(&mut self) {
+        if self.is_running() {
+            self.fade_out = self.last_out;
+            self.fade_gain = 1.0;
+            self.follower.reset();
+        }
+    }
+
     pub fn trigger(&mut self, trigger_seq: u64, trigger_time: i64, sound: &SoundData) {
         let trigger = match sound.patch.play_mode {
             PlayMode::Poly => true, // Poly: Always retrigger
@@ -271,24 +513,31 @@ impl Voice {
         };
         self.trigger_seq = trigger_seq;
         if trigger {
+            self.random = rand::random::<Float>() * 2.0 - 1.0;
+            self.rel_velocity = 0.0;
             if !self.is_running() {
+                self.follower.reset();
                 for osc in self.osc.iter_mut() {
                     osc.reset(trigger_time);
+                    if self.unison.phase > 0.0 {
+                        osc.set_phase(rand::random::<Float>() * self.unison.phase);
+                    }
                 }
             }
             for i in 0..NUM_ENVELOPES {
+                self.env[i].set_note(self.key, self.velocity);
                 self.env[i].trigger(trigger_time, &sound.env[i]);
             }
             for (i, lfo) in self.lfo.iter_mut().enumerate() {
-                lfo.reset(trigger_time, sound.lfo[i].phase);
+                lfo.trigger(trigger_time, &sound.lfo[i]);
             }
         }
         self.triggered = true;
     }
 
-    // TODO: Release velocity
-    pub fn key_release(&mut self, _velocity: u8, pedal_held: bool, sound: &SoundData) {
+    pub fn key_release(&mut self, velocity: u8, pedal_held: bool, sound: &SoundData) {
         self.triggered = false;
+        self.rel_velocity = velocity as Float / 127.0;
         if !pedal_held {
             self.release_envelopes(sound);
         }
@@ -308,9 +557,96 @@ impl Voice {
         self.triggered || self.env[0].is_running()
     }
 
+    /** Current level of the amplitude envelope, including velocity. */
+    pub fn get_level(&self) -> Float {
+        self.env[0].get_level() * self.scaled_vel
+    }
+
     fn release_envelopes(&mut self, sound: &SoundData) {
         for i in 0..NUM_ENVELOPES {
             self.env[i].release(self.last_update, &sound.env[i]);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ModAccumulator, ModTable};
+
+    use wavetable::WtManager;
+
+    #[test]
+    fn stolen_note_fades_out() {
+        let mut wt_manager = WtManager::new(1000.0, "data");
+        wt_manager.add_basic_tables(0);
+        let mut voice = Voice::new(1000, wt_manager.get_table(0).unwrap());
+        let mut sound = SoundData::new();
+        sound.init();
+        let mut sound_local = sound;
+        let global_state = SynthState{freq_factor: 1.0, mod_acc: ModAccumulator::new(), modul: ModTable::new()};
+        voice.set_freq(100.0);
+        voice.set_velocity(100, 0.0);
+
+        voice.trigger(0, 0, &sound);
+        assert_eq!(voice.fade_gain, 0.0); // Nothing to fade when starting from silence
+        for t in 0..100 {
+            voice.get_sample(t, &sound, &mut sound_local, &global_state);
+        }
+        let last_out = voice.last_out;
+        assert!(last_out[0] != 0.0);
+
+        // Retriggering the running voice doesn't fade
+        voice.trigger(1, 100, &sound);
+        assert_eq!(voice.fade_gain, 0.0);
+
+        // Stealing the running voice fades out its last output
+        voice.steal();
+        voice.trigger(2, 100, &sound);
+        assert_eq!(voice.fade_gain, 1.0);
+        assert_eq!(voice.fade_out, last_out);
+        let num_fade_samples = (STEAL_FADE_TIME * 1000.0) as i64;
+        for t in 100..100 + num_fade_samples {
+            voice.get_sample(t, &sound, &mut sound_local, &global_state);
+        }
+        assert!(voice.fade_gain < 0.0001);
+    }
+
+    #[test]
+    fn new_note_starts_with_zero_level() {
+        let mut wt_manager = WtManager::new(1000.0, "data");
+        wt_manager.add_basic_tables(0);
+        let mut voice = Voice::new(1000, wt_manager.get_table(0).unwrap());
+        let mut sound = SoundData::new();
+        sound.init();
+        let mut sound_local = sound;
+        let global_state = SynthState{freq_factor: 1.0, mod_acc: ModAccumulator::new(), modul: ModTable::new()};
+        voice.set_freq(100.0);
+        voice.set_velocity(100, 0.0);
+
+        // A finished note
+        voice.trigger(0, 0, &sound);
+        let mut t = 0;
+        while t < 100 {
+            voice.get_sample(t, &sound, &mut sound_local, &global_state);
+            t += 1;
+        }
+        voice.key_release(0, false, &sound);
+        while voice.is_running() && t < 100000 {
+            voice.get_sample(t, &sound, &mut sound_local, &global_state);
+            t += 1;
+        }
+        assert!(voice.follower.get_level() > 0.0);
+        voice.trigger(1, t, &sound);
+        assert_eq!(voice.follower.get_level(), 0.0);
+
+        // A stolen note
+        for t in t..t + 100 {
+            voice.get_sample(t, &sound, &mut sound_local, &global_state);
+        }
+        assert!(voice.follower.get_level() > 0.0);
+        voice.steal();
+        voice.trigger(2, t + 100, &sound);
+        assert_eq!(voice.follower.get_level(), 0.0);
+    }
+}
@@ -145,6 +145,13 @@ impl WtOsc {
         }
     }
 
+    // Set the position in the wave cycle, 0.0 - 1.0.
+    pub fn set_phase(&mut self, phase: Float) {
+        for i in 0..MAX_VOICES {
+            self.last_pos[i] = phase * NUM_SAMPLES_PER_TABLE as Float;
+        }
+    }
+
 }
 
 #[cfg(test)]
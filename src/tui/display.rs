@@ -276,7 +276,7 @@ impl Display {
         println!("<F3>     : Load default sound bank\r");
         println!("<Ctrl-C> : Copy current sound\r");
         println!("<Ctrl-V> : Paste copied sound to current patch\r");
-        println!("<Ctrl-N> : Rename the current patch\r");
+        println!("<Ctrl-N> : Rename the current patch or the selected macro\r");
         println!("<F7>     : Cycle through color schemes\r");
         println!("<F12>    : Quit Yazz\r");
         println!("\r");
@@ -60,5 +60,6 @@ use super::SynthMessage;
 use super::{Parameter, ParameterValue, ParamId, FunctionId, SynthParam, MenuItem, FUNCTIONS, MOD_SOURCES};
 use super::UiMessage;
 use super::WtInfo;
+use super::NUM_MACROS;
 use super::{SOUND_DATA_VERSION, SYNTH_ENGINE_VERSION};
 use super::value_range::ValueRange;
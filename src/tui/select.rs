@@ -1357,12 +1357,12 @@ fn function_id_can_be_entered_directly() {
     context.handle_input(TestInput::Chars("o2v".to_string()));
     assert!(context.verify_selection(Parameter::Oscillator, 2, Parameter::Voices, ParameterValue::Int(1)));
 
-    // Modulators have an ID range > 10, but < 20. Entering a '2' should
+    // Modulators have an ID range > 60, but < 70. Entering a '7' should
     // directly switch to the parameter selection, since adding another digit
     // would produce an invalid ID.
-    let c: &[TestInput] = &[TestInput::Key(Key::Esc), TestInput::Chars("m2a".to_string())];
+    let c: &[TestInput] = &[TestInput::Key(Key::Esc), TestInput::Chars("m7a".to_string())];
     assert_eq!(context.handle_inputs(c), false);
-    assert!(context.verify_selection(Parameter::Modulation, 2, Parameter::Amount, ParameterValue::Float(0.0)));
+    assert!(context.verify_selection(Parameter::Modulation, 7, Parameter::Amount, ParameterValue::Float(0.0)));
 
     // Entering a '1' should wait for a possible second digit.
     let c: &[TestInput] = &[TestInput::Key(Key::Esc), TestInput::Chars("m12a".to_string())];
@@ -1381,9 +1381,9 @@ fn tempstring_for_function_id_is_cleared() {
 
     // On next selection, the tempstring should be cleared, so a single digit
     // value will work.
-    let c: &[TestInput] = &[TestInput::Key(Key::Esc), TestInput::Chars("m2a".to_string())];
+    let c: &[TestInput] = &[TestInput::Key(Key::Esc), TestInput::Chars("m7a".to_string())];
     assert_eq!(context.handle_inputs(c), false);
-    assert!(context.verify_selection(Parameter::Modulation, 2, Parameter::Amount, ParameterValue::Float(0.0)));
+    assert!(context.verify_selection(Parameter::Modulation, 7, Parameter::Amount, ParameterValue::Float(0.0)));
 }
 
 #[test]
@@ -1816,7 +1816,7 @@ fn bracket_in_state_value_updates_value_state_to_match_value_type() {
     let mut context = TestContext::new();
 
     // From state_value to state_value_function
-    context.handle_input(TestInput::Chars("m2a".to_string()));
+    context.handle_input(TestInput::Chars("m7a".to_string()));
     assert_eq!(context.ps.state, SelectorState::Value);
     assert!(context.verify_parameter(Parameter::Amount));
     context.handle_input(TestInput::Chars("[".to_string()));
@@ -1825,7 +1825,7 @@ fn bracket_in_state_value_updates_value_state_to_match_value_type() {
 
     // From state_value_function to state_value
     context.handle_input(TestInput::Key(Key::Esc));
-    context.handle_input(TestInput::Chars("m2t".to_string()));
+    context.handle_input(TestInput::Chars("m7t".to_string()));
     assert_eq!(context.ps.state, SelectorState::ValueFunction);
     assert!(context.verify_parameter(Parameter::Target));
     context.handle_input(TestInput::Chars("]".to_string()));
@@ -1834,7 +1834,7 @@ fn bracket_in_state_value_updates_value_state_to_match_value_type() {
 
     // From state_value_function_id to state_value
     context.handle_input(TestInput::Key(Key::Esc));
-    context.handle_input(TestInput::Chars("m2to".to_string()));
+    context.handle_input(TestInput::Chars("m7to".to_string()));
     assert_eq!(context.ps.state, SelectorState::ValueFunctionIndex);
     assert!(context.verify_parameter(Parameter::Target));
     context.handle_input(TestInput::Chars("]".to_string()));
@@ -1843,7 +1843,7 @@ fn bracket_in_state_value_updates_value_state_to_match_value_type() {
 
     // From state_value_parameter to state_value
     context.handle_input(TestInput::Key(Key::Esc));
-    context.handle_input(TestInput::Chars("m2to1".to_string()));
+    context.handle_input(TestInput::Chars("m7to1".to_string()));
     assert_eq!(context.ps.state, SelectorState::ValueParam);
     assert!(context.verify_parameter(Parameter::Target));
     context.handle_input(TestInput::Chars("]".to_string()));
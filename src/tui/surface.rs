@@ -4,6 +4,7 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use super::{Parameter, ParamId, ParameterValue, SoundData, UiMessage};
+use super::{NUM_MACROS, Observer};
 use super::{Bar, Button, Canvas, CanvasRef, Container, ContainerRef, Controller,
             Dial, Index, Label, MouseHandler, ObserverRef, Printer ,ColorScheme,
             Slider, Value, ValueDisplay, Widget};
@@ -15,6 +16,7 @@ pub struct Surface {
     controller: Controller<ParamId>,
     mod_targets: HashMap<ParamId, ObserverRef>, // Maps the modulation indicator to the corresponding parameter key
     mouse_handler: MouseHandler<ParamId>,
+    macro_labels: Vec<Rc<RefCell<Label<ParamId>>>>, // Labels showing the macro names
     pub canvas: CanvasRef<ParamId>,
 }
 
@@ -30,6 +32,7 @@ impl Surface {
                                controller,
                                mod_targets,
                                mouse_handler,
+                               macro_labels: vec!(),
                                canvas};
 
         let osc: ContainerRef<ParamId> = Rc::new(RefCell::new(Container::new()));
@@ -90,8 +93,14 @@ impl Surface {
         let sysinfo: ContainerRef<ParamId> = Rc::new(RefCell::new(Container::new()));
         sysinfo.borrow_mut().enable_border(true);
         this.add_sysinfo(&mut sysinfo.borrow_mut(), 1, 0);
+        let (_, sysinfo_height) = sysinfo.borrow().get_size();
         this.add_child(sysinfo, 95, 0);
 
+        let macros: ContainerRef<ParamId> = Rc::new(RefCell::new(Container::new()));
+        macros.borrow_mut().enable_border(true);
+        this.add_macros(&mut macros.borrow_mut(), 1, 0);
+        this.add_child(macros, 95, sysinfo_height);
+
         this.window.set_position(1, 1);
         this.window.set_color_scheme(colors);
         this
@@ -195,6 +204,19 @@ impl Surface {
         Rc::new(RefCell::new(c))
     }
 
+    fn new_macro_dial(&mut self,
+                      key: &ParamId) -> ContainerRef<ParamId> {
+        let mut c = Container::new();
+        let label = Label::new(format!("Macro {}", key.function_id), 10);
+        let dial = Dial::new(Value::Float(0.0), Value::Float(1.0), Value::Float(0.0));
+        dial.borrow_mut().set_key(*key);
+        self.controller.add_observer(key, dial.clone());
+        self.macro_labels.push(label.clone());
+        c.add_child(label, 0, 1);
+        c.add_child(dial, 10, 1);
+        Rc::new(RefCell::new(c))
+    }
+
     fn new_label_value_int(&mut self,
                            label: &str,
                            value: i64,
@@ -399,12 +421,12 @@ impl Surface {
         target.add_child(filter_gain, x_offset, 4 + y_offset);
 
         key.set(Parameter::Filter, func_id, Parameter::EnvDepth);
-        let filter_edepth = self.new_mod_dial_float("EnvDepth", 0.0, 1.0, 0.0, false, &key);
+        let filter_edepth = self.new_mod_dial_float("EnvDepth", -1.0, 1.0, 0.0, false, &key);
         target.add_child(filter_edepth, 14 + x_offset, 4 + y_offset);
 
         key.set(Parameter::Filter, func_id, Parameter::KeyFollow);
-        let filter_follow = self.new_option("KeyFollow", 0, &key);
-        target.add_child(filter_follow, x_offset, 8 + y_offset);
+        let filter_follow = self.new_mod_dial_float("KeyFollow", -100.0, 200.0, 0.0, false, &key);
+        target.add_child(filter_follow, x_offset, 7 + y_offset);
     }
 
     fn add_delay(&mut self,
@@ -467,6 +489,24 @@ impl Surface {
         target.add_child(bpm, x_offset, 7 + y_offset);
     }
 
+    fn add_macros(&mut self,
+                  target: &mut Container<ParamId>,
+                  x_offset: Index,
+                  y_offset: Index) {
+        let title = "Macros";
+        let len = title.len();
+        let title = Label::new(title.to_string(), len as Index);
+        target.add_child(title, x_offset, y_offset);
+
+        for i in 0..NUM_MACROS {
+            let key = ParamId::new(Parameter::Macro, i + 1, Parameter::Level);
+            let dial = self.new_macro_dial(&key);
+            let x = (i % 2) as Index * 14;
+            let y = (i / 2) as Index * 3;
+            target.add_child(dial, x + x_offset, 1 + y + y_offset);
+        }
+    }
+
     fn add_sysinfo(&mut self,
                    target: &mut Container<ParamId>,
                    x_offset: Index,
@@ -501,6 +541,12 @@ impl Surface {
         }
     }
 
+    pub fn set_macro_names(&mut self, names: &[String]) {
+        for (label, name) in self.macro_labels.iter().zip(names) {
+            label.borrow_mut().update(Value::Str(name.clone()));
+        }
+    }
+
     pub fn set_sound_info(&mut self, program: usize, name: &str) {
         let name = (program + 1).to_string() + ": " + name;
         self.window.set_title(name);
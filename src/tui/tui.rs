@@ -73,6 +73,7 @@ pub struct Tui {
     ctrl_map: CtrlMap,              // Mapping of MIDI controller to parameter
     active_ctrl_set: usize,
     temp_name: String,
+    renamed_macro: Option<usize>,   // Macro being renamed, None for the patch name
     last_value: SynthParam,         // Copy of the last value set via controller
 
     // State machine for ParamSelector
@@ -121,6 +122,7 @@ impl Tui {
             ctrl_map: CtrlMap::new(),
             active_ctrl_set: 0,
             temp_name: "".to_string(),
+            renamed_macro: None,
             last_value: SynthParam{..Default::default()},
             selector_sm: StateMachine::new(ParamSelector::state_function),
             mode: Mode::Edit,
@@ -265,10 +267,14 @@ impl Tui {
                         self.select_sound(self.selected_sound);
                         true
                     }
-                    'n' => { // Rename sound
+                    'n' => { // Rename sound, or the selected macro
                         self.state = TuiState::Name;
+                        self.renamed_macro = self.get_selected_macro();
                         self.temp_name.clear();
-                        self.temp_name.push_str(&self.sound.borrow().name);
+                        match self.renamed_macro {
+                            Some(id) => self.temp_name.push_str(&self.sound.borrow().macro_names[id]),
+                            None => self.temp_name.push_str(&self.sound.borrow().name),
+                        }
                         self.display_name_prompt();
                         true
                     }
@@ -352,6 +358,12 @@ impl Tui {
                         self.temp_name.push(c);
                         TuiState::Name
                     }
+                    '\n' if self.renamed_macro.is_some() => {
+                        let id = self.renamed_macro.unwrap();
+                        self.sound.borrow_mut().macro_names[id] = self.temp_name.clone();
+                        self.window.set_macro_names(&self.sound.borrow().macro_names);
+                        TuiState::Play
+                    }
                     '\n' => {
                         self.sound.borrow_mut().name = self.temp_name.clone();
                         //self.bank.set_sound(self.selected_sound, &self.sound.borrow());
@@ -377,6 +389,15 @@ impl Tui {
         next_state
     }
 
+    // Get the index of the macro currently selected in the parameter selector.
+    fn get_selected_macro(&self) -> Option<usize> {
+        let func = &self.selector.func_selection;
+        if func.item_list[func.item_index].item != Parameter::Macro {
+            return None;
+        }
+        if let ParameterValue::Int(x) = func.value { Some(x as usize - 1) } else { None }
+    }
+
     fn scan_wavetables(&mut self) {
         let re = Regex::new(r"(.*).wav").unwrap();
         if !Path::new("data").exists() {
@@ -434,6 +455,7 @@ impl Tui {
         let sound = &mut self.sound.borrow_mut();
         sound.name = sound_ref.name.clone();
         sound.data = sound_ref.data;
+        sound.macro_names = sound_ref.macro_names.clone();
 
         // Send new sound to synth engine
         let sound_copy = Box::new(sound.data);
@@ -441,6 +463,7 @@ impl Tui {
 
         // Update display
         self.window.set_sound_info(self.selected_sound, &sound.name);
+        self.window.set_macro_names(&sound.macro_names);
         self.window.update_all(&sound.data);
     }
 
@@ -482,7 +505,11 @@ impl Tui {
                         // controller mappings below
                         self.sender.send(SynthMessage::Midi(*m)).unwrap();
                     }
-                    _ => (),
+                    _ => {
+                        // All other controllers can be used as global mod
+                        // sources
+                        self.sender.send(SynthMessage::Midi(*m)).unwrap();
+                    }
                 }
 
                 // All controllers (including ModWheel and sustain pedal) might
@@ -675,7 +702,8 @@ impl Tui {
 
     fn display_name_prompt(&mut self) {
         self.printer.set_color(self.current_color.bg_base, self.current_color.fg_base);
-        print!("{}{} Patch name: ", cursor::Goto(1, 1), clear::CurrentLine);
+        let prompt = if self.renamed_macro.is_some() { "Macro name" } else { "Patch name" };
+        print!("{}{} {}: ", cursor::Goto(1, 1), clear::CurrentLine, prompt);
         self.printer.set_color(self.current_color.bg_base, self.current_color.fg_base_l);
         print!("{}", self.temp_name);
         stdout().flush().ok();
@@ -128,6 +128,22 @@ impl ValueRange {
             result
         }
     }
+
+    /** Adds a modulation value to a parameter value, keeps result within value range.
+     *
+     * Int and Choice values are rounded to the nearest valid value.
+     */
+    pub fn add_modulation(&self, value: ParameterValue, modulation: Float) -> ParameterValue {
+        let result = self.safe_add(value.as_float(), modulation);
+        match value {
+            ParameterValue::Int(_) => ParameterValue::Int(result.round() as i64),
+            ParameterValue::Choice(_) => {
+                let (_, max) = self.get_min_max();
+                ParameterValue::Choice(result.round().min(max - 1.0) as usize)
+            }
+            _ => ParameterValue::Float(result),
+        }
+    }
 }
 
 impl Default for ValueRange {